@@ -0,0 +1,73 @@
+//! How many voices one core renders in real time, sending each sample through a channel
+//! versus rendering blocks into the ring buffer. Run with `cargo bench`.
+
+use std::{sync::mpsc, thread, time::Instant};
+use rust_synth::core::{
+    control::{BLOCK_SIZE, synth::{self, Command::*, id}},
+    music_theory::{Hz, pitch::Pitch},
+    synth::{Frame, instrument},
+};
+use rust_synth::preset;
+use rust_synth::util::ring_buffer;
+
+const SAMPLE_RATE: Hz = 44100.;
+const VOICES: usize = 32;
+const SECONDS: f64 = 2.;
+
+fn synth() -> synth::State {
+    let mut synth = synth::State::new(SAMPLE_RATE);
+    let specs = instrument::Specs { max_voices: VOICES as u8, ..preset::saw_pad() };
+    synth.interpret(SetPatch(specs));
+    for i in 0..VOICES {
+        let pitch = Pitch::from_index(36 + i);
+        synth.interpret(NoteOn(pitch, 1., id(pitch)));
+    }
+    synth
+}
+
+fn frames() -> usize {
+    (SAMPLE_RATE * SECONDS) as usize
+}
+
+fn per_sample() -> f64 {
+    let mut synth = synth();
+    let (sound_out, sound_in) = mpsc::sync_channel::<Frame>(1024);
+    let reader = thread::spawn(move || sound_in.iter().count());
+    let start = Instant::now();
+    for _ in 0..frames() {
+        sound_out.send(synth.next_sample()).unwrap();
+    }
+    drop(sound_out);
+    reader.join().unwrap();
+    start.elapsed().as_secs_f64()
+}
+
+fn per_block() -> f64 {
+    let mut synth = synth();
+    let (mut sound_out, mut sound_in) = ring_buffer::ring_buffer(1024);
+    let total = frames() / BLOCK_SIZE * BLOCK_SIZE;
+    let reader = thread::spawn(move || {
+        let mut count = 0;
+        while count < total {
+            match sound_in.pop() {
+                Some(_) => count += 1,
+                None => thread::yield_now(),
+            }
+        }
+    });
+    let start = Instant::now();
+    let mut block = [Frame::default(); BLOCK_SIZE];
+    for _ in 0..total / BLOCK_SIZE {
+        synth.process(&mut block);
+        sound_out.push_all(&block).unwrap();
+    }
+    reader.join().unwrap();
+    start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    for (name, elapsed) in [("per sample", per_sample()), ("per block", per_block())] {
+        let voices_per_core = VOICES as f64 * SECONDS / elapsed;
+        println!("{:>10}: {:.3}s for {}s of {} voices, {:.0} voices per core", name, elapsed, SECONDS, VOICES, voices_per_core);
+    }
+}
@@ -0,0 +1,47 @@
+//! Renders a MIDI file to WAV without a sound card.
+//!
+//! Usage: render <midi file> <wav file> [--rate <hz>] [--format 16|24|float] [--stems]
+
+use std::{env, process};
+use rust_synth::io::{render::{self, Settings}, wav::Format};
+
+const USAGE: &str = "Usage: render <midi file> <wav file> [--rate <hz>] [--format 16|24|float] [--stems]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = parse(&args)
+        .and_then(|(midi_path, wav_path, settings)| render::render_midi(&midi_path, &wav_path, settings));
+    match result {
+        Ok(file_paths) => file_paths.iter().for_each(|path| println!("Wrote: {}", path)),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        },
+    }
+}
+
+fn parse(args: &[String]) -> Result<(String, String, Settings), String> {
+    let mut paths = vec![];
+    let mut settings = Settings::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rate" => settings.sample_rate = args.next()
+                .and_then(|rate| rate.parse().ok())
+                .filter(|rate| *rate > 0)
+                .ok_or_else(|| format!("Invalid sample rate. {}", USAGE))?,
+            "--format" => settings.format = match args.next().map(String::as_str) {
+                Some("16") => Format::Int16,
+                Some("24") => Format::Int24,
+                Some("float") => Format::Float,
+                _ => return Err(format!("Invalid format. {}", USAGE)),
+            },
+            "--stems" => settings.stems = true,
+            path => paths.push(path.to_string()),
+        }
+    }
+    match paths.as_slice() {
+        [midi_path, wav_path] => Ok((midi_path.clone(), wav_path.clone(), settings)),
+        _ => Err(USAGE.to_string()),
+    }
+}
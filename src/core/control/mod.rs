@@ -2,3 +2,6 @@
 pub mod tools;
 pub mod synth;
 pub mod sheet_music;
+
+/// Frames rendered between command and view updates
+pub const BLOCK_SIZE: usize = 64;
@@ -1,33 +1,78 @@
-use std::sync::mpsc::SyncSender;
-use std::collections::HashMap;
-use crate::core::{control::synth, music_theory::Hz, synth::Sample};
+use std::{collections::HashMap, mem, time::Duration};
+use crate::core::{control::{BLOCK_SIZE, synth}, music_theory::Hz, synth::{Frame, Sample, Seconds}};
+use crate::util::ring_buffer::Producer;
 use crate::core::sheet_music::{sheet_music::*, playing_music::*};
 
 ///
 /// Orchestrates synths to play commands from sheet music
 ///
 
-pub fn start(sample_rate: Hz, music: SheetMusic, signal_out: SyncSender<Sample>) {
+pub fn start(sample_rate: Hz, music: SheetMusic, mut signal_out: Producer) {
     let mut state = State::new(sample_rate, music);
+    let mut block = [Frame::default(); BLOCK_SIZE];
     loop {
-        state.tick_music();
-        let sample = state.next_sample();
-        signal_out.send(sample).expect("Failed to send a sample");
+        let elapsed = state.music.elapsed();
+        state.tick_music(elapsed);
+        state.process(&mut block);
+        signal_out.push_all(&block).expect("Failed to send samples");
     }
 }
 
-struct State {
+/// One channel's output
+pub type Stem = (ChannelId, Vec<Frame>);
+
+/// Plays the whole piece as fast as possible, on a clock counting rendered frames instead of real time.
+/// `out` gets every block split by channel, until the notes still sounding after the last event fade out.
+pub fn render(sample_rate: Hz, music: SheetMusic, mut out: impl FnMut(&[Stem]) -> Result<(), String>) -> Result<(), String> {
+    let mut state = State::new(sample_rate, music);
+    let end = state.music.duration().as_secs_f64();
+    let mut stems: Vec<Stem> = state.synths.keys().map(|channel| (*channel, vec![Frame::default(); BLOCK_SIZE])).collect();
+    stems.sort_by_key(|(channel, _)| *channel);
+    for block in 0.. {
+        let time: Seconds = (block * BLOCK_SIZE) as f64 / sample_rate;
+        state.tick_music(Duration::from_secs_f64(time));
+        let mut loudest: Sample = 0.;
+        for (channel, frames) in stems.iter_mut() {
+            if let Some(synth) = state.synths.get_mut(channel) {
+                synth.process(frames);
+            }
+            loudest = frames.iter().fold(loudest, |max, frame| max.max(frame.left.abs()).max(frame.right.abs()));
+        }
+        out(&stems)?;
+        if time > end + MAX_TAIL || (time > end && loudest < SILENCE) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Commands due at once before the buffer has to grow
+const COMMANDS_CAPACITY: usize = 64;
+/// Quieter than this, notes sounding after the end count as faded out
+const SILENCE: Sample = 1e-4;
+/// Longest rendered after the last event, in case something never fades out
+const MAX_TAIL: Seconds = 10.;
+
+pub struct State {
     synths: HashMap<ChannelId, synth::State>,
     music: PlayingMusic,
+    tempo: Option<Tempo>,
+    /// Holds one synth's output while mixing
+    buffer: Vec<Frame>,
+    /// Reused on every tick to avoid allocating
+    commands: Vec<TargetedCommand>,
 }
 
 impl State {
-    fn new(sample_rate: Hz, sheet_music: SheetMusic) -> State {
+    pub fn new(sample_rate: Hz, sheet_music: SheetMusic) -> State {
         State {
             synths: sheet_music.voices.iter()
                 .map(|track| (track.instrument_id, synth::State::new(sample_rate)))
                 .collect(),
             music: PlayingMusic::new(sheet_music),
+            tempo: None,
+            buffer: Vec::with_capacity(BLOCK_SIZE),
+            commands: Vec::with_capacity(COMMANDS_CAPACITY),
         }
     }
 
@@ -38,14 +83,55 @@ impl State {
         }
     }
 
-    fn tick_music(&mut self) {
-        self.music.next().commands.into_iter()
-            .for_each(|cmd| self.interpret(cmd));
+    /// Sends the commands due by `elapsed` to the synths
+    pub fn tick_music(&mut self, elapsed: Duration) {
+        let mut commands = mem::take(&mut self.commands);
+        let tempo = self.music.next(elapsed, &mut commands).section.beat_duration;
+        if tempo > 0 && self.tempo != Some(tempo) {
+            self.tempo = Some(tempo);
+            let beat = Duration::from_micros(u64::from(tempo));
+            self.synths.values_mut().for_each(|synth| synth.interpret(synth::Command::SetTempo(beat)));
+        }
+        commands.drain(..).for_each(|cmd| self.interpret(cmd));
+        self.commands = commands;
     }
 
-    fn next_sample(&mut self) -> Sample {
-        self.synths.values_mut()
-            .map(|i| i.next_sample())
-            .sum()
+    /// Mixes all synths
+    pub fn process(&mut self, out: &mut [Frame]) {
+        out.fill(Frame::default());
+        self.buffer.resize(out.len(), Frame::default());
+        for synth in self.synths.values_mut() {
+            synth.process(&mut self.buffer);
+            out.iter_mut().zip(&self.buffer).for_each(|(mix, frame)| *mix += *frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::control::synth::{Command::*, id};
+    use crate::core::music_theory::pitch::Pitch;
+
+    #[test]
+    fn renders_until_notes_fade_out() {
+        let pitch = Pitch::from_index(60);
+        let music = SheetMusic {
+            sections: vec![Section { tick_duration: Duration::from_millis(1), ..Default::default() }],
+            voices: vec![Voice::new(vec![(NoteOn(pitch, 1., id(pitch)), 0), (NoteOff(id(pitch)), 100)], 3)],
+            end: 100,
+            ..Default::default()
+        };
+        let mut frames = 0;
+        let mut loudest: Sample = 0.;
+        render(1000., music, |stems| {
+            assert_eq!(stems.len(), 1);
+            assert_eq!(stems[0].0, 3);
+            frames += stems[0].1.len();
+            loudest = stems[0].1.iter().fold(loudest, |max, frame| max.max(frame.left.abs()));
+            Ok(())
+        }).unwrap();
+        assert!(loudest > 0.01);
+        assert!(frames > 100 && frames < 1000, "frames: {}", frames);
     }
 }
@@ -1,7 +1,7 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 use crate::core::{
     music_theory::{Hz, pitch::Pitch},
-    synth::{Sample, Velocity, instrument::{self, Instrument}},
+    synth::{Frame, Pan, Velocity, instrument::{self, Instrument}},
 };
 
 ///
@@ -9,12 +9,23 @@ use crate::core::{
 ///
 
 #[derive(Clone, PartialEq, Debug)]
+#[allow(clippy::large_enum_variant)] // SetPatch is rare enough not to be worth boxing
 pub enum Command {
     NoteOn(Pitch, Velocity, Id), NoteOff(Id),
     ModXY(f64, f64),
+    /// From -1 to 1
+    PitchBend(f64),
+    /// From 0 to 1
+    ModWheel(f64), Aftertouch(f64),
+    Pan(Pan),
+    /// Duration of a beat, for tempo synced modulation
+    SetTempo(Duration),
     SetPatch(instrument::Specs),
 }
 
+/// Notes held at once before the map has to grow
+const HOLDING_CAPACITY: usize = 128;
+
 pub struct State {
     sample_rate: Hz,
     instrument: Instrument,
@@ -33,8 +44,8 @@ impl State {
         let specs = instrument::Specs::default();
         State {
             sample_rate,
-            instrument: Instrument::new(specs, sample_rate),
-            holding_notes: HashMap::new(),
+            instrument: Instrument::new(specs, sample_rate).expect("Invalid default instrument"),
+            holding_notes: HashMap::with_capacity(HOLDING_CAPACITY),
         }
     }
 
@@ -43,11 +54,20 @@ impl State {
             Command::NoteOn(pitch, velocity, id) => self.handle_note_on(pitch, velocity, id),
             Command::NoteOff(id) => self.handle_note_off(id),
             Command::ModXY(x, y) => self.instrument.set_xy_params(x, y),
+            Command::PitchBend(amount) => self.instrument.set_pitch_bend(amount),
+            Command::ModWheel(value) => self.instrument.set_mod_wheel(value),
+            Command::Aftertouch(value) => self.instrument.set_aftertouch(value),
+            Command::Pan(pan) => self.instrument.set_pan(pan),
+            Command::SetTempo(beat) => self.instrument.set_tempo(beat.as_secs_f64()),
             Command::SetPatch(specs) => self.set_specs(specs),
         }
     }
 
-    pub fn next_sample(&mut self) -> Sample {
+    pub fn process(&mut self, out: &mut [Frame]) {
+        self.instrument.process(out)
+    }
+
+    pub fn next_sample(&mut self) -> Frame {
         self.instrument.next_sample()
     }
 
@@ -70,10 +90,16 @@ impl State {
         }
     }
 
+    /// Keeps the current instrument if the new one is invalid
     fn set_specs(&mut self, specs: instrument::Specs) {
-        let state = self.instrument.get_state();
-        self.instrument = Instrument::new(specs, self.sample_rate);
-        self.instrument.set_state(state);
+        match Instrument::new(specs, self.sample_rate) {
+            Ok(instrument) => {
+                let state = self.instrument.get_state();
+                self.instrument = instrument;
+                self.instrument.set_state(state);
+            },
+            Err(e) => eprintln!("Invalid patch: {}", e),
+        }
     }
 
 }
@@ -1,32 +1,28 @@
 use std::sync::mpsc::{Receiver, SyncSender};
-use std::time::Duration;
+use std::{mem, time::Duration};
 use crate::core::{
-    control::{synth::{self, Command::*}},
+    control::{BLOCK_SIZE, synth::{self, Command::*}},
     music_theory::{Hz, pitch_class::PitchClass},
-    synth::{instrument, Sample},
+    synth::{instrument, Frame},
     tools::{pulse, transposer, loops, arpeggiator, arpeggiator::phrase::Phrase, tap_tempo, Millis},
     sheet_music::sheet_music::MeasurePosition,
 };
+use crate::util::ring_buffer::Producer;
 
 ///
 /// Connects tools and synth together, interprets commands and delegates to them
 ///
 
-pub fn start(sample_rate: Hz, command_in: Receiver<Command>, sound_out: SyncSender<Sample>, view_out: SyncSender<View>) {
-    let command_rate = 10; //TODO in hz
-    let view_refresh_rate = 1000; //TODO in hz
+pub fn start(sample_rate: Hz, command_in: Receiver<Command>, mut sound_out: Producer, view_out: SyncSender<View>) {
+    let view_refresh_rate = 16; //TODO in hz
     let mut state = State::new(sample_rate);
+    let mut block = [Frame::default(); BLOCK_SIZE];
     for i in 0.. {
-        if i % command_rate == 0 {
-            if let Ok(command) = command_in.try_recv() {
-                state.interpret(command);
-            }
-            state.tick_arpeggiator();
-        }
+        command_in.try_iter().for_each(|command| state.interpret(command));
+        state.tick_arpeggiator();
 
-        let new_sample = state.next_sample();
-        sound_out.send(new_sample).expect("Failed to send a sample");
-        state.loops.write(new_sample);
+        state.process(&mut block);
+        sound_out.push_all(&block).expect("Failed to send samples");
 
         if i % view_refresh_rate == 0 {
             let view = state.view();
@@ -38,8 +34,11 @@ pub fn start(sample_rate: Hz, command_in: Receiver<Command>, sound_out: SyncSend
 const BEATS_PER_MEASURE: u64 = 4;
 const PULSES_PER_BEAT: u64 = 32;
 const DEFAULT_PULSE: Millis = 12;
+/// Commands from one arpeggiator tick before the buffer has to grow
+const ARP_COMMANDS_CAPACITY: usize = 16;
 
 #[derive(Clone)]
+#[allow(clippy::large_enum_variant)] // As big as synth::Command, which keeps its rare SetPatch unboxed
 pub enum Command {
     Instrument(synth::Command),
     Transposer(transposer::Command),
@@ -50,7 +49,8 @@ pub enum Command {
 
 #[derive(Clone)]
 pub enum Patch {
-    Instrument(instrument::Specs),
+    /// Boxed, patches are kept in lists and cloned
+    Instrument(Box<instrument::Specs>),
     ArpeggiatorPhrase(Option<Phrase>), //TODO deprecate
     Arpeggiator(Option<arpeggiator::Specs>),
     Noop,
@@ -62,6 +62,8 @@ pub struct State {
     pulse: pulse::Pulse,
     arpeggiator: Option<arpeggiator::Arpeggiator>,
     arp_index: f64,
+    /// Reused on every tick to avoid allocating
+    arp_commands: Vec<synth::Command>,
     tap_tempo: tap_tempo::TapTempo,
     loops: loops::Manager,
 }
@@ -78,19 +80,22 @@ pub struct View {
 }
 
 impl State {
-    fn new(sample_rate: Hz) -> State {
+    pub fn new(sample_rate: Hz) -> State {
+        let mut synth = synth::State::new(sample_rate);
+        synth.interpret(SetTempo(Duration::from_millis(DEFAULT_PULSE * PULSES_PER_BEAT)));
         State {
-            synth: synth::State::new(sample_rate),
+            synth,
             transposer: transposer::State::new(PitchClass::C),
             tap_tempo: Default::default(),
             pulse: pulse::Pulse::new_with_millis(DEFAULT_PULSE),
             arpeggiator: None,
             arp_index: 0.,
-            loops: Default::default(),
+            arp_commands: Vec::with_capacity(ARP_COMMANDS_CAPACITY),
+            loops: loops::Manager::new(sample_rate),
         }
     }
 
-    fn interpret(&mut self, command: Command) {
+    pub fn interpret(&mut self, command: Command) {
         match command {
             Command::Instrument(cmd) => self.play_or_arpeggiate(cmd),
             Command::Transposer(cmd) => self.transposer.interpret(cmd),
@@ -123,7 +128,7 @@ impl State {
 
     fn set_patch(&mut self, patch: Patch) {
         match patch {
-            Patch::Instrument(specs) => self.synth.interpret(SetPatch(specs)),
+            Patch::Instrument(specs) => self.synth.interpret(SetPatch(*specs)),
             Patch::Arpeggiator(specs) => self.set_arpeggiator(specs),
             Patch::ArpeggiatorPhrase(seq) => self.set_arpeggiator_phrase(seq),
             Patch::Noop => (),
@@ -157,17 +162,21 @@ impl State {
         if let Some(beat) = self.tap_tempo.read() {
             let pulse_period = Duration::from_millis(beat / PULSES_PER_BEAT);
             self.pulse = self.pulse.with_period(pulse_period);
+            self.synth.interpret(SetTempo(Duration::from_millis(beat)));
         }
     }
 
-    fn tick_arpeggiator(&mut self) {
+    pub fn tick_arpeggiator(&mut self) {
         if let Some(measure_progress) = self.tick_around_measure() {
             let from = self.arp_index;
             let to = self.arp_index + measure_progress;
             self.arp_index = to;
-            self.arpeggiator.as_mut()
-                .map(|arp| arp.next(from, to)).unwrap_or_else(Vec::default)
-                .into_iter().for_each(|cmd| self.play_transposed(cmd));
+            let mut commands = mem::take(&mut self.arp_commands);
+            if let Some(arp) = self.arpeggiator.as_mut() {
+                arp.next(from, to, &mut commands);
+            }
+            commands.drain(..).for_each(|cmd| self.play_transposed(cmd));
+            self.arp_commands = commands;
         }
     }
 
@@ -179,10 +188,13 @@ impl State {
         })
     }
 
-    fn next_sample(&mut self) -> Sample {
-        let new_sample = self.synth.next_sample();
-        let loop_sample = self.loops.next_sample();
-        loop_sample + new_sample
+    /// Mixes playing loops over the synth and records the result
+    pub fn process(&mut self, out: &mut [Frame]) {
+        self.synth.process(out);
+        for frame in out.iter_mut() {
+            *frame += self.loops.next_frame();
+            self.loops.write(*frame);
+        }
     }
 
     pub fn view(&self) -> View {
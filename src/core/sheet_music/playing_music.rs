@@ -6,31 +6,39 @@ pub struct PlayingMusic {
     sections: Vec<Section>,
     voices: Vec<PlayingVoice>,
     begin: Instant,
-    #[allow(dead_code)] end: Instant,
+    duration: Duration,
     current_section_index: usize,
 }
 impl PlayingMusic {
 
     pub fn new(sheet_music: SheetMusic) -> Self {
-        let begin = Instant::now();
         PlayingMusic {
-            begin,
-            end: begin + music_duration(&sheet_music),
+            begin: Instant::now(),
+            duration: music_duration(&sheet_music),
             sections: sheet_music.sections,
             voices: sheet_music.voices.into_iter().map(PlayingVoice::new).collect(),
             current_section_index: 0,
         }
     }
 
-    pub fn next(&mut self) -> Reading {
-        let time_elapsed = Instant::now() - self.begin;
+    /// Since playing started, in real time
+    pub fn elapsed(&self) -> Duration {
+        Instant::now() - self.begin
+    }
+
+    /// Until the last event
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Appends the commands that are due by `time_elapsed` to `commands`
+    pub fn next(&mut self, time_elapsed: Duration, commands: &mut Vec<TargetedCommand>) -> Reading {
         self.update_current_section_index(time_elapsed);
         let section = self.sections.get(self.current_section_index)
             .unwrap_or_else(|| panic!("No current section"));
-        let commands =self.voices.iter_mut()
-            .flat_map(|t| t.next_targeted(time_elapsed, section))
-            .collect();
-        Reading { commands, time_elapsed, section }
+        self.voices.iter_mut()
+            .for_each(|t| t.next_targeted(time_elapsed, section, commands));
+        Reading { time_elapsed, section }
     }
 
     fn update_current_section_index(&mut self, elapsed_time: Duration) {
@@ -58,21 +66,16 @@ impl PlayingVoice {
         PlayingVoice { voice, current_event_index: 0 }
     }
 
-    fn next_targeted(&mut self, elapsed_time: Duration, section: &Section) -> Vec<TargetedCommand> {
-        self.next(elapsed_time, section).into_iter()
-            .map(|c| (c, self.voice.instrument_id))
-            .collect()
-    }
-
-    fn next(&mut self, elapsed_time: Duration, section: &Section) -> Vec<Command> {
+    fn next_targeted(&mut self, elapsed_time: Duration, section: &Section, commands: &mut Vec<TargetedCommand>) {
         let begin = self.current_event_index;
-        let result: Vec<Command> = self.voice.events.iter()
+        let instrument_id = self.voice.instrument_id;
+        let due = self.voice.events.iter()
             .skip(begin)
-            .take_while(|(_, t)| get_time(*t, section) <= elapsed_time)
-            .map(|(cmd, _)| cmd.clone())
-            .collect();
-        self.current_event_index += result.len();
-        result
+            .take_while(|(_, t)| get_time(*t, section) <= elapsed_time);
+        for (cmd, _) in due {
+            commands.push((cmd.clone(), instrument_id));
+            self.current_event_index += 1;
+        }
     }
 
 }
@@ -90,7 +93,6 @@ fn get_time(tick: Tick, section: &Section) -> Duration {
 }
 
 pub struct Reading<'a> {
-    pub commands: Vec<TargetedCommand>,
     pub time_elapsed: Duration,
     pub section: &'a Section,
 }
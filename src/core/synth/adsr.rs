@@ -1,11 +1,40 @@
 use super::{Sample, Seconds, Proportion};
 
+/// Steepness of the curved shapes
+const CURVATURE: f64 = 5.;
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Adsr {
     pub attack: Seconds,
     pub decay: Seconds,
     pub sustain: Proportion,
     pub release: Seconds,
+    pub attack_curve: Curve,
+    pub decay_curve: Curve,
+    pub release_curve: Curve,
+}
+
+/// How a segment moves from one level to the next
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub enum Curve {
+    #[default]
+    Linear,
+    /// Fast at first, slowing down as it gets close, like an analog envelope
+    Exponential,
+    /// Slow at first, speeding up towards the end
+    Logarithmic,
+}
+
+impl Curve {
+    /// Proportion of the way covered at `progress` through the segment
+    pub fn apply(&self, progress: Proportion) -> Proportion {
+        let progress = progress.clamp(0., 1.);
+        match self {
+            Curve::Linear => progress,
+            Curve::Exponential => (1. - (-CURVATURE * progress).exp()) / (1. - (-CURVATURE).exp()),
+            Curve::Logarithmic => ((CURVATURE * progress).exp() - 1.) / (CURVATURE.exp() - 1.),
+        }
+    }
 }
 impl Adsr {
     pub fn new(attack: Seconds, decay: Seconds, sustain: Proportion, release: Seconds) -> Adsr {
@@ -13,28 +42,151 @@ impl Adsr {
         assert!(decay >= 0., "decay was: {}", decay);
         assert!((0. ..=1.).contains(&sustain), "sustain was: {}", sustain);
         assert!(release >= 0., "release was: {}", release);
-        Adsr { attack, decay, sustain, release }
+        Adsr { attack, decay, sustain, release, ..Default::default() }
+    }
+
+    pub fn with_curves(self, attack_curve: Curve, decay_curve: Curve, release_curve: Curve) -> Adsr {
+        Adsr { attack_curve, decay_curve, release_curve, ..self }
+    }
+
+    /// Same as a `Dahdsr` without delay, hold or loop
+    pub fn apply(&self, elapsed: Seconds, elapsed_since_release: Seconds, sample: Sample) -> Sample {
+        Dahdsr::from(*self).apply(elapsed, elapsed_since_release, sample)
+    }
+}
+
+///
+/// Delay, attack, hold, decay, sustain, release. Can loop through some of the stages while
+/// holding the note, e.g. attack to decay for a repeating pulse. Used for modulation.
+///
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Dahdsr {
+    pub delay: Seconds,
+    pub attack: Seconds,
+    pub hold: Seconds,
+    pub decay: Seconds,
+    pub sustain: Proportion,
+    pub release: Seconds,
+    pub attack_curve: Curve,
+    pub decay_curve: Curve,
+    pub release_curve: Curve,
+    pub looping: Option<Loop>,
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum Stage { Delay, Attack, Hold, Decay }
+
+/// Repeats from the start of one stage to the end of another, including both
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Loop {
+    pub from: Stage,
+    pub to: Stage,
+}
+
+impl Dahdsr {
+    pub fn new(delay: Seconds, attack: Seconds, hold: Seconds, decay: Seconds, sustain: Proportion, release: Seconds) -> Dahdsr {
+        assert!(delay >= 0., "delay was: {}", delay);
+        assert!(hold >= 0., "hold was: {}", hold);
+        Dahdsr { delay, hold, looping: None, ..Dahdsr::from(Adsr::new(attack, decay, sustain, release)) }
+    }
+
+    pub fn with_curves(self, attack_curve: Curve, decay_curve: Curve, release_curve: Curve) -> Dahdsr {
+        Dahdsr { attack_curve, decay_curve, release_curve, ..self }
+    }
+
+    pub fn with_loop(self, from: Stage, to: Stage) -> Dahdsr {
+        assert!(from <= to, "loop was from {:?} to {:?}", from, to);
+        Dahdsr { looping: Some(Loop { from, to }), ..self }
     }
 
     pub fn apply(&self, elapsed: Seconds, elapsed_since_release: Seconds, sample: Sample) -> Sample {
         sample * self.scale_ratio(elapsed, elapsed_since_release)
     }
 
+    /// `elapsed` counts from the start of the note, including the release
     fn scale_ratio(&self, elapsed: Seconds, elapsed_since_release: Seconds) -> Proportion {
         if elapsed_since_release > 0. {
+            let released_level = self.holding_level(elapsed - elapsed_since_release);
             let release_progress = elapsed_since_release / self.release;
-            let release_scale = (1. - release_progress).max(0.);
-            self.sustain * release_scale
-        } else if elapsed < self.attack {
-            elapsed / self.attack
-        } else if elapsed < self.attack + self.decay {
-            let decay_progress = (elapsed - self.attack) / self.decay;
-            let sustain_head_room = 1. - self.sustain;
-            self.sustain + sustain_head_room * (1. - decay_progress)
+            released_level * (1. - self.release_curve.apply(release_progress))
+        } else {
+            self.holding_level(elapsed)
+        }
+    }
+
+    fn holding_level(&self, elapsed: Seconds) -> Proportion {
+        let elapsed = self.looped(elapsed);
+        let attack_start = self.stage_start(Stage::Attack);
+        let hold_start = self.stage_start(Stage::Hold);
+        let decay_start = self.stage_start(Stage::Decay);
+        if elapsed < attack_start {
+            0.
+        } else if elapsed < hold_start {
+            self.attack_curve.apply((elapsed - attack_start) / self.attack)
+        } else if elapsed < decay_start {
+            1.
+        } else if elapsed < decay_start + self.decay {
+            let decay_progress = (elapsed - decay_start) / self.decay;
+            1. - (1. - self.sustain) * self.decay_curve.apply(decay_progress)
         } else {
             self.sustain
         }
     }
+
+    /// Brings a time past the end of the loop back into it
+    fn looped(&self, elapsed: Seconds) -> Seconds {
+        match self.looping {
+            Some(Loop { from, to }) => {
+                let start = self.stage_start(from);
+                let length = self.stage_start(to) + self.stage_duration(to) - start;
+                if length > 0. && elapsed >= start + length {
+                    start + (elapsed - start) % length
+                } else {
+                    elapsed
+                }
+            },
+            None => elapsed,
+        }
+    }
+
+    fn stage_start(&self, stage: Stage) -> Seconds {
+        [Stage::Delay, Stage::Attack, Stage::Hold, Stage::Decay].iter()
+            .take_while(|s| **s < stage)
+            .map(|s| self.stage_duration(*s))
+            .sum()
+    }
+
+    fn stage_duration(&self, stage: Stage) -> Seconds {
+        match stage {
+            Stage::Delay => self.delay,
+            Stage::Attack => self.attack,
+            Stage::Hold => self.hold,
+            Stage::Decay => self.decay,
+        }
+    }
+}
+
+impl From<Adsr> for Dahdsr {
+    fn from(adsr: Adsr) -> Self {
+        Dahdsr {
+            delay: 0.,
+            attack: adsr.attack,
+            hold: 0.,
+            decay: adsr.decay,
+            sustain: adsr.sustain,
+            release: adsr.release,
+            attack_curve: adsr.attack_curve,
+            decay_curve: adsr.decay_curve,
+            release_curve: adsr.release_curve,
+            looping: None,
+        }
+    }
+}
+
+impl Default for Dahdsr {
+    fn default() -> Self {
+        Dahdsr::from(Adsr::default())
+    }
 }
 
 impl Default for Adsr {
@@ -43,14 +195,17 @@ impl Default for Adsr {
             attack: 0.,
             decay: 0.,
             sustain: 1.,
-            release: 0.
+            release: 0.,
+            attack_curve: Curve::Linear,
+            decay_curve: Curve::Linear,
+            release_curve: Curve::Linear,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Adsr;
+    use super::{Adsr, Curve, Dahdsr, Stage};
 
     fn sut() -> Adsr {
         Adsr::new(1.,  1., 0.5, 1.)
@@ -93,6 +248,65 @@ mod tests {
         assert_approx(sut().apply(1.,   0.5,   0.1), 0.025);
     }
 
+    #[test]
+    fn release_from_current_level() {
+        assert_approx(sut().apply(0.5,  0.,    0.1), 0.05);
+        assert_approx(sut().apply(0.75, 0.25,  0.1), 0.0375);
+        assert_approx(sut().apply(1.,   0.5,   0.1), 0.025);
+        assert_approx(sut().apply(1.5,  0.25,  0.1), 0.065625);
+        assert_approx(sut().apply(2.5,  1.,    0.1), 0.);
+    }
+
+    #[test]
+    fn curves() {
+        for curve in [Curve::Linear, Curve::Exponential, Curve::Logarithmic] {
+            assert_approx(curve.apply(0.), 0.);
+            assert!((curve.apply(1.) - 1.).abs() < 1e-12);
+            assert_approx(curve.apply(-1.), 0.);
+        }
+        assert!(Curve::Exponential.apply(0.25) > 0.5);
+        assert!(Curve::Logarithmic.apply(0.75) < 0.5);
+    }
+
+    #[test]
+    fn curved_segments() {
+        let adsr = sut().with_curves(Curve::Logarithmic, Curve::Exponential, Curve::Exponential);
+        assert!(adsr.apply(0.5, 0., 1.) < 0.5 * 0.5);
+        assert!(adsr.apply(1.5, 0., 1.) < 0.75 - 0.1);
+        assert!(adsr.apply(3.5, 0.5, 1.) < 0.25 * 0.5);
+        assert_approx(adsr.apply(4., 1., 1.), 0.);
+        assert_approx(adsr.apply(1., 0., 1.), 1.);
+    }
+
+    #[test]
+    fn same_as_adsr() {
+        let dahdsr = Dahdsr::from(sut());
+        for (elapsed, since_release) in [(0.25, 0.), (1.5, 0.), (3., 0.), (0.75, 0.25), (3., 0.5)] {
+            assert_approx(dahdsr.apply(elapsed, since_release, 0.1), sut().apply(elapsed, since_release, 0.1));
+        }
+    }
+
+    #[test]
+    fn delay_and_hold() {
+        let dahdsr = Dahdsr::new(1., 1., 1., 1., 0.5, 1.);
+        assert_approx(dahdsr.apply(0.5, 0., 1.), 0.);
+        assert_approx(dahdsr.apply(1.5, 0., 1.), 0.5);
+        assert_approx(dahdsr.apply(2.5, 0., 1.), 1.);
+        assert_approx(dahdsr.apply(3.5, 0., 1.), 0.75);
+        assert_approx(dahdsr.apply(5., 0., 1.), 0.5);
+        assert_approx(dahdsr.apply(3., 0.5, 1.), 0.5);
+    }
+
+    #[test]
+    fn loops_while_holding() {
+        let dahdsr = Dahdsr::new(1., 1., 0., 1., 0., 1.).with_loop(Stage::Attack, Stage::Decay);
+        assert_approx(dahdsr.apply(1.5, 0., 1.), 0.5);
+        assert_approx(dahdsr.apply(3.5, 0., 1.), 0.5);
+        assert_approx(dahdsr.apply(4.5, 0., 1.), 0.5);
+        assert_approx(dahdsr.apply(102.5, 0., 1.), 0.5);
+        assert_approx(dahdsr.apply(4., 0.5, 1.), 0.25);
+    }
+
     fn assert_approx(left: f64, right: f64) {
         assert!((right - left).abs() < 0.0000000000000001)
     }
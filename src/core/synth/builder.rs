@@ -1,50 +1,121 @@
-use super::{Seconds, Proportion, instrument::{self, ModTarget, ModSpecs}, oscillator, filter, adsr::Adsr, lfo};
+use super::{Seconds, Proportion, Pan, modulated::Polarity, instrument::{self, ModTarget, ModSource, ModSlot, VelocityCurve, VoiceMode, VoiceStealing, FilterMode}, oscillator, filter, adsr::{Adsr, Dahdsr, Curve}, lfo,
+            section::{self, Layer, Sub}};
+use crate::core::music_theory::{Octave, Semitones};
 
 pub struct Builder {
     max_voices: u8,
-    oscillator: oscillator::Specs,
+    voice_mode: VoiceMode,
+    voice_stealing: VoiceStealing,
+    velocity_curve: VelocityCurve,
+    oscillators: section::Specs,
     filter: filter::Specs,
-    lfo: Option<lfo::Specs>,
+    filter_mode: FilterMode,
+    lfos: Vec<lfo::Specs>,
+    mod_envelopes: Vec<Dahdsr>,
+    mod_matrix: Vec<ModSlot>,
     adsr: Adsr,
     volume: Proportion,
-    modulation_x: ModTarget,
-    modulation_y: ModTarget,
-    pub modulation_lfo: ModSpecs,
+    pan: Pan,
+    glide: Seconds,
+    bend_range: Semitones,
+    smoothing: Seconds,
 }
 impl Builder {
 
     pub fn osc(oscillator: oscillator::Specs) -> Builder {
         Builder {
-            oscillator,
+            oscillators: section::Specs::single(oscillator),
             max_voices: 8,
+            voice_mode: VoiceMode::Poly,
+            voice_stealing: VoiceStealing::Oldest,
+            velocity_curve: VelocityCurve::Linear,
             filter: filter::Specs::default(),
-            lfo: None,
+            filter_mode: FilterMode::Global,
+            lfos: vec![],
+            mod_envelopes: vec![],
+            mod_matrix: vec![
+                ModSlot::new(ModSource::X, ModTarget::Filter(filter::ModTarget::Cutoff), 1.),
+                ModSlot::new(ModSource::Y, ModTarget::Filter(filter::ModTarget::QFactor), 1.),
+            ],
             adsr: Adsr::new(0., 0.05, 0.8, 0.2),
             volume: 0.2,
-            modulation_x: ModTarget::Filter(filter::ModTarget::Cutoff),
-            modulation_y: ModTarget::Filter(filter::ModTarget::QFactor),
-            modulation_lfo: ModSpecs{ target: ModTarget::Noop, amount: 1.},
+            pan: 0.,
+            glide: 0.,
+            bend_range: 2,
+            smoothing: instrument::DEFAULT_SMOOTHING,
         }
     }
 
     pub fn build(self) -> instrument::Specs {
         instrument::Specs {
             max_voices: self.max_voices,
-            oscillator: self.oscillator,
+            voice_mode: self.voice_mode,
+            voice_stealing: self.voice_stealing,
+            velocity_curve: self.velocity_curve,
+            oscillators: self.oscillators,
             filter: self.filter,
-            lfo: self.lfo,
+            filter_mode: self.filter_mode,
+            lfos: self.lfos,
+            mod_envelopes: self.mod_envelopes,
+            mod_matrix: self.mod_matrix,
             adsr: self.adsr,
             volume: self.volume,
-            modulation_x: self.modulation_x,
-            modulation_y: self.modulation_y,
-            modulation_lfo: self.modulation_lfo,
+            pan: self.pan,
+            glide: self.glide,
+            bend_range: self.bend_range,
+            smoothing: self.smoothing,
         }
     }
 
+    /// Ignored past `section::MAX_LAYERS`
+    pub fn add_osc(mut self, oscillator: oscillator::Specs) -> Self {
+        if self.oscillators.layers.len() < section::MAX_LAYERS {
+            self.oscillators.layers.push(Layer::new(oscillator));
+        }
+        self
+    }
+    /// Layer setters ignore layers that weren't added
+    pub fn tune(mut self, layer: usize, octave: Octave, semitones: Semitones, cents: f64) -> Self {
+        if let Some(layer) = self.oscillators.layers.get_mut(layer) {
+            layer.octave = octave;
+            layer.semitones = semitones;
+            layer.cents = cents;
+        }
+        self
+    }
+    pub fn level(mut self, layer: usize, value: Proportion) -> Self {
+        if let Some(layer) = self.oscillators.layers.get_mut(layer) {
+            layer.level = value;
+        }
+        self
+    }
+    pub fn pan(mut self, layer: usize, value: Pan) -> Self {
+        if let Some(layer) = self.oscillators.layers.get_mut(layer) {
+            layer.pan = value;
+        }
+        self
+    }
+    pub fn sub(mut self, shape: oscillator::Basic, octave: Octave, level: Proportion) -> Self {
+        self.oscillators.sub = Some(Sub { shape, octave, level });
+        self
+    }
+    pub fn sync(mut self) -> Self {
+        self.oscillators.sync = true;
+        self
+    }
+    pub fn ring(mut self) -> Self {
+        self.oscillators.ring = true;
+        self
+    }
     pub fn filter(mut self, value: filter::Specs) -> Self {
         self.filter = value;
         self
     }
+    /// Filters each voice on its own, shifting the cutoff by an envelope
+    pub fn filter_envelope(mut self, a: Seconds, d: Seconds, s: Proportion, r: Seconds, amount: Proportion) -> Self {
+        self.filter_mode = FilterMode::PerVoice { envelope: Adsr::new(a, d, s, r), amount };
+        self
+    }
     pub fn attack(mut self, value: Seconds) -> Self {
         self.adsr.attack = value;
         self
@@ -61,25 +132,77 @@ impl Builder {
         self.adsr.release = value;
         self
     }
-    pub fn lfo(mut self, value: lfo::Specs, target: ModTarget, amount: Proportion) -> Self {
-        self.lfo = Some(value);
-        self.modulation_lfo = ModSpecs{ target, amount };
+    /// Adds an LFO routed to `target`, more routes can be added with `modulate`
+    pub fn lfo(mut self, value: lfo::Specs, target: ModTarget, amount: f64) -> Self {
+        let source = ModSource::Lfo(self.lfos.len());
+        self.lfos.push(value);
+        self.modulate(source, target, amount)
+    }
+    /// Adds an envelope routed to `target`, restarting with each voice
+    pub fn mod_envelope(mut self, value: impl Into<Dahdsr>, target: ModTarget, amount: f64) -> Self {
+        let source = ModSource::Envelope(self.mod_envelopes.len());
+        self.mod_envelopes.push(value.into());
+        self.modulate(source, target, amount)
+    }
+    /// Negative amounts move the target down
+    pub fn modulate(mut self, source: ModSource, target: ModTarget, amount: f64) -> Self {
+        self.mod_matrix.push(ModSlot::new(source, target, amount));
+        self
+    }
+    /// The target swings both ways around its base, the middle of the source leaves it unchanged
+    pub fn modulate_bipolar(mut self, source: ModSource, target: ModTarget, amount: f64) -> Self {
+        self.mod_matrix.push(ModSlot::new(source, target, amount).with_polarity(Polarity::Bipolar));
         self
     }
     pub fn adsr(mut self, a: Seconds, d: Seconds, s: Proportion, r: Seconds) -> Self {
         self.adsr = Adsr::new(a, d, s, r);
         self
     }
+    pub fn curves(mut self, attack: Curve, decay: Curve, release: Curve) -> Self {
+        self.adsr = self.adsr.with_curves(attack, decay, release);
+        self
+    }
     pub fn volume(mut self, value: Proportion) -> Self {
         self.volume = value;
         self
     }
-    pub fn mod_x(mut self, target: ModTarget) -> Self {
-        self.modulation_x = target;
+    /// Of the whole instrument, see `pan` for a single layer
+    pub fn instrument_pan(mut self, value: Pan) -> Self {
+        self.pan = value;
+        self
+    }
+    pub fn voice_mode(mut self, value: VoiceMode) -> Self {
+        self.voice_mode = value;
+        self
+    }
+    pub fn voice_stealing(mut self, value: VoiceStealing) -> Self {
+        self.voice_stealing = value;
         self
     }
-    pub fn mod_y(mut self, target: ModTarget) -> Self {
-        self.modulation_y = target;
+    pub fn velocity_curve(mut self, value: VelocityCurve) -> Self {
+        self.velocity_curve = value;
         self
     }
+    pub fn glide(mut self, value: Seconds) -> Self {
+        self.glide = value;
+        self
+    }
+    pub fn bend_range(mut self, value: Semitones) -> Self {
+        self.bend_range = value;
+        self
+    }
+    pub fn smoothing(mut self, value: Seconds) -> Self {
+        self.smoothing = value;
+        self
+    }
+    pub fn mod_x(self, target: ModTarget) -> Self {
+        self.replace_slot(ModSource::X, target)
+    }
+    pub fn mod_y(self, target: ModTarget) -> Self {
+        self.replace_slot(ModSource::Y, target)
+    }
+    fn replace_slot(mut self, source: ModSource, target: ModTarget) -> Self {
+        self.mod_matrix.retain(|slot| slot.source != source);
+        self.modulate(source, target, 1.)
+    }
 }
\ No newline at end of file
@@ -8,11 +8,15 @@ pub(super) struct BiquadFilter{
     sample_rate: Hz,
     cutoff: ModParam,
     qfactor: ModParam,
+    gain: ModParam,
     filter_type: Box<dyn FilterType>,
-    input_history: [Sample;2],
-    output_history: [Sample;2],
+    key_tracking: Proportion,
+    velocity_sensitivity: Proportion,
 }
 
+/// Input history then output history, oldest first
+const STATE_SIZE: usize = 4;
+
 impl BiquadFilter {
     pub(super) fn new(sample_rate: Hz, specs: Specs) -> BiquadFilter {
         assert!(sample_rate > 0., "sample_rate was: {}", sample_rate);
@@ -21,35 +25,44 @@ impl BiquadFilter {
             TypeSpec::HPF => Box::new(Hpf),
             TypeSpec::BPF => Box::new(Bpf),
             TypeSpec::Notch => Box::new(Notch),
+            TypeSpec::LowShelf => Box::new(LowShelf),
+            TypeSpec::HighShelf => Box::new(HighShelf),
+            TypeSpec::Peaking => Box::new(Peaking),
+            TypeSpec::AllPass => Box::new(AllPass),
         };
         BiquadFilter {
             sample_rate, filter_type,
             cutoff: ModParam::with_base(specs.cutoff, MIN_CUTOFF, MAX_CUTOFF),
             qfactor: ModParam::with_base(specs.resonance, MIN_QFACTOR, MAX_QFACTOR),
-            input_history: [0., 0.],
-            output_history: [0., 0.],
+            gain: ModParam::with_base(specs.gain, -MAX_GAIN_DB, MAX_GAIN_DB),
+            key_tracking: specs.key_tracking,
+            velocity_sensitivity: specs.velocity_sensitivity,
         }
     }
 
-    fn calculate_coefficients(&mut self) -> Coefficients {
-        let cutoff = self.cutoff.calculate();
+    fn calculate_coefficients(&self, cutoff_mod: CutoffMod) -> Coefficients {
+        let cutoff = cutoff(&self.cutoff, cutoff_mod, self.sample_rate);
         let qfactor = self.qfactor.calculate();
         let w0 = 2. * PI * cutoff / self.sample_rate;
         let alpha = w0.sin() / (2. * qfactor);
-        self.filter_type.coefficients(w0, alpha)
+        let a = 10_f64.powf(self.gain.calculate() / 40.);
+        self.filter_type.coefficients(w0, alpha, a)
     }
 }
 
 impl Filter for BiquadFilter {
-    fn filter(&mut self, input: Sample) -> Sample {
-        let coef = &self.calculate_coefficients();
+    fn state_size(&self) -> usize { STATE_SIZE }
+
+    fn filter(&self, state: &mut [f64], input: Sample, cutoff_mod: CutoffMod) -> Sample {
+        let coef = &self.calculate_coefficients(cutoff_mod);
+        let (input_history, output_history) = state.split_at_mut(2);
         let a0 = coef.a0;
         let output = (coef.b0/a0) * input
-            + (coef.b1/a0) * self.input_history[1]  + (coef.b2/a0) * self.input_history[0]
-            - (coef.a1/a0) * self.output_history[1] - (coef.a2/a0) * self.output_history[0];
+            + (coef.b1/a0) * input_history[1]  + (coef.b2/a0) * input_history[0]
+            - (coef.a1/a0) * output_history[1] - (coef.a2/a0) * output_history[0];
 
-        self.input_history  = [self.input_history[1], input];
-        self.output_history = [self.output_history[1], output];
+        input_history.copy_from_slice(&[input_history[1], input]);
+        output_history.copy_from_slice(&[output_history[1], output]);
 
         output
     }
@@ -58,7 +71,11 @@ impl Filter for BiquadFilter {
         View {
             cutoff: self.cutoff.normalized(),
             resonance: self.qfactor.normalized(),
+            key_tracking: self.key_tracking,
+            velocity_sensitivity: self.velocity_sensitivity,
+            gain: self.gain.normalized(),
             filter_type: self.filter_type.spec(),
+            model: Model::Biquad,
         }
     }
 }
@@ -68,6 +85,7 @@ impl Modulated<ModTarget> for BiquadFilter {
         match target {
             ModTarget::Cutoff => Some(&mut self.cutoff),
             ModTarget::QFactor => Some(&mut self.qfactor),
+            ModTarget::Gain => Some(&mut self.gain),
         }
     }
 }
@@ -77,13 +95,14 @@ struct Coefficients {
 }
 
 trait FilterType {
-    fn coefficients(&self, w0: f64, alpha: f64) -> Coefficients;
+    /// `a` is the amplitude for shelving and peaking types, the square root of the gain
+    fn coefficients(&self, w0: f64, alpha: f64, a: f64) -> Coefficients;
     fn spec(&self) -> TypeSpec;
 }
 
 struct Lpf;
 impl FilterType for Lpf {
-    fn coefficients(&self, w0: f64, alpha: f64) -> Coefficients {
+    fn coefficients(&self, w0: f64, alpha: f64, _a: f64) -> Coefficients {
         let cos_w0 = w0.cos();
         Coefficients {
             b0: (1. - cos_w0) / 2.,
@@ -102,7 +121,7 @@ impl FilterType for Lpf {
 
 struct Hpf;
 impl FilterType for Hpf {
-    fn coefficients(&self, w0: f64, alpha: f64) -> Coefficients {
+    fn coefficients(&self, w0: f64, alpha: f64, _a: f64) -> Coefficients {
         let cos_w0 = w0.cos();
         Coefficients{
             b0:  (1. + cos_w0)/2.,
@@ -121,7 +140,7 @@ impl FilterType for Hpf {
 
 struct Bpf;
 impl FilterType for Bpf {
-    fn coefficients(&self, w0: f64, alpha: f64) -> Coefficients {
+    fn coefficients(&self, w0: f64, alpha: f64, _a: f64) -> Coefficients {
         let sin_w0 = w0.sin();
         let cos_w0 = w0.cos();
         Coefficients{
@@ -141,7 +160,7 @@ impl FilterType for Bpf {
 
 struct Notch;
 impl FilterType for Notch {
-    fn coefficients(&self, w0: f64, alpha: f64) -> Coefficients {
+    fn coefficients(&self, w0: f64, alpha: f64, _a: f64) -> Coefficients {
         let cos_w0 = w0.cos();
         Coefficients{
             b0:   1.,
@@ -157,3 +176,126 @@ impl FilterType for Notch {
         TypeSpec::Notch
     }
 }
+
+struct LowShelf;
+impl FilterType for LowShelf {
+    fn coefficients(&self, w0: f64, alpha: f64, a: f64) -> Coefficients {
+        let cos_w0 = w0.cos();
+        let sqrt_a_alpha = 2. * a.sqrt() * alpha;
+        Coefficients{
+            b0:      a * ((a + 1.) - (a - 1.) * cos_w0 + sqrt_a_alpha),
+            b1: 2. * a * ((a - 1.) - (a + 1.) * cos_w0),
+            b2:      a * ((a + 1.) - (a - 1.) * cos_w0 - sqrt_a_alpha),
+            a0:           (a + 1.) + (a - 1.) * cos_w0 + sqrt_a_alpha,
+            a1:    -2. * ((a - 1.) + (a + 1.) * cos_w0),
+            a2:           (a + 1.) + (a - 1.) * cos_w0 - sqrt_a_alpha,
+        }
+    }
+
+    fn spec(&self) -> TypeSpec {
+        TypeSpec::LowShelf
+    }
+}
+
+struct HighShelf;
+impl FilterType for HighShelf {
+    fn coefficients(&self, w0: f64, alpha: f64, a: f64) -> Coefficients {
+        let cos_w0 = w0.cos();
+        let sqrt_a_alpha = 2. * a.sqrt() * alpha;
+        Coefficients{
+            b0:       a * ((a + 1.) + (a - 1.) * cos_w0 + sqrt_a_alpha),
+            b1: -2. * a * ((a - 1.) + (a + 1.) * cos_w0),
+            b2:       a * ((a + 1.) + (a - 1.) * cos_w0 - sqrt_a_alpha),
+            a0:            (a + 1.) - (a - 1.) * cos_w0 + sqrt_a_alpha,
+            a1:      2. * ((a - 1.) - (a + 1.) * cos_w0),
+            a2:            (a + 1.) - (a - 1.) * cos_w0 - sqrt_a_alpha,
+        }
+    }
+
+    fn spec(&self) -> TypeSpec {
+        TypeSpec::HighShelf
+    }
+}
+
+struct Peaking;
+impl FilterType for Peaking {
+    fn coefficients(&self, w0: f64, alpha: f64, a: f64) -> Coefficients {
+        let cos_w0 = w0.cos();
+        Coefficients{
+            b0:   1. + alpha * a,
+            b1:  -2. * cos_w0,
+            b2:   1. - alpha * a,
+            a0:   1. + alpha / a,
+            a1:  -2. * cos_w0,
+            a2:   1. - alpha / a,
+        }
+    }
+
+    fn spec(&self) -> TypeSpec {
+        TypeSpec::Peaking
+    }
+}
+
+struct AllPass;
+impl FilterType for AllPass {
+    fn coefficients(&self, w0: f64, alpha: f64, _a: f64) -> Coefficients {
+        let cos_w0 = w0.cos();
+        Coefficients{
+            b0:   1. - alpha,
+            b1:  -2. * cos_w0,
+            b2:   1. + alpha,
+            a0:   1. + alpha,
+            a1:  -2. * cos_w0,
+            a2:   1. - alpha,
+        }
+    }
+
+    fn spec(&self) -> TypeSpec {
+        TypeSpec::AllPass
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: Hz = 44100.;
+
+    fn peak(filter_type: TypeSpec, gain: f64, freq: Hz) -> Sample {
+        let specs = Specs { filter_type, cutoff: 1000. / MAX_CUTOFF, resonance: 0., gain, ..Default::default() };
+        let filter = BiquadFilter::new(SAMPLE_RATE, specs);
+        let mut state = [0.; STATE_SIZE];
+        (0..8820).map(|i| (2. * PI * freq * i as f64 / SAMPLE_RATE).sin())
+            .map(|input| filter.filter(&mut state, input, CutoffMod::default()).abs())
+            .skip(4410)
+            .fold(0., f64::max)
+    }
+
+    fn assert_db(amplitude: Sample, db: f64) {
+        let actual = 20. * amplitude.log10();
+        assert!((actual - db).abs() < 0.5, "{} dB != {} dB", actual, db);
+    }
+
+    #[test]
+    fn peaking_boosts_and_cuts_at_cutoff() {
+        assert_db(peak(TypeSpec::Peaking, 1., 1000.), MAX_GAIN_DB);
+        assert_db(peak(TypeSpec::Peaking, 0., 1000.), -MAX_GAIN_DB);
+        assert_db(peak(TypeSpec::Peaking, 0.5, 1000.), 0.);
+        assert_db(peak(TypeSpec::Peaking, 1., 20.), 0.);
+    }
+
+    #[test]
+    fn shelves() {
+        assert_db(peak(TypeSpec::LowShelf, 0.75, 30.), MAX_GAIN_DB / 2.);
+        assert_db(peak(TypeSpec::LowShelf, 0.75, 15000.), 0.);
+        assert_db(peak(TypeSpec::HighShelf, 0.75, 30.), 0.);
+        assert_db(peak(TypeSpec::HighShelf, 0.75, 15000.), MAX_GAIN_DB / 2.);
+    }
+
+    #[test]
+    fn all_pass_keeps_amplitude() {
+        for freq in [100., 1000., 10000.] {
+            assert_db(peak(TypeSpec::AllPass, 0.5, freq), 0.);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use super::*;
+use std::f64::consts::PI;
+
+/// Feedback at full resonance, a bit over 4 so it oscillates on its own
+const MAX_FEEDBACK: f64 = 4.2;
+/// Input gain at full drive
+const MAX_DRIVE: f64 = 8.;
+
+/// 4 one pole lowpass stages in a feedback loop, solved without a unit delay as in
+/// Vadim Zavalishin's "The Art of VA Filter Design", with tanh saturation on the input and on the loop.
+/// Other responses are mixed from the stage outputs.
+pub(super) struct Ladder {
+    sample_rate: Hz,
+    cutoff: ModParam,
+    qfactor: ModParam,
+    filter_type: TypeSpec,
+    drive: Proportion,
+    key_tracking: Proportion,
+    velocity_sensitivity: Proportion,
+}
+
+impl Ladder {
+    pub(super) fn new(sample_rate: Hz, specs: Specs, drive: Proportion) -> Ladder {
+        assert!(sample_rate > 0., "sample_rate was: {}", sample_rate);
+        Ladder {
+            sample_rate,
+            filter_type: specs.filter_type,
+            cutoff: ModParam::with_base(specs.cutoff, MIN_CUTOFF, MAX_CUTOFF),
+            qfactor: ModParam::with_base(specs.resonance, MIN_QFACTOR, MAX_QFACTOR),
+            drive: drive.clamp(0., 1.),
+            key_tracking: specs.key_tracking,
+            velocity_sensitivity: specs.velocity_sensitivity,
+        }
+    }
+}
+
+impl Filter for Ladder {
+    /// One integrator per stage
+    fn state_size(&self) -> usize { 4 }
+
+    fn filter(&self, state: &mut [f64], input: Sample, cutoff_mod: CutoffMod) -> Sample {
+        let cutoff = cutoff(&self.cutoff, cutoff_mod, self.sample_rate);
+        let g = (PI * cutoff / self.sample_rate).tan();
+        let gain = g / (1. + g);
+        let feedback = self.qfactor.normalized() * MAX_FEEDBACK;
+
+        // The last stage output is gain^4 * u plus what the integrators hold
+        let held = state.iter().fold(0., |sum, s| sum * gain + s / (1. + g));
+        let driven = (input * (1. + self.drive * (MAX_DRIVE - 1.))).tanh();
+        let u = ((driven - feedback * held) / (1. + feedback * gain.powi(4))).tanh();
+
+        let mut stages = [0.; 4];
+        let mut stage_input = u;
+        for (stage, s) in stages.iter_mut().zip(state.iter_mut()) {
+            let v = (stage_input - *s) * gain;
+            *stage = v + *s;
+            *s = *stage + v;
+            stage_input = *stage;
+        }
+
+        let [y1, y2, y3, y4] = stages;
+        match self.filter_type {
+            TypeSpec::HPF => u - 4. * y1 + 6. * y2 - 4. * y3 + y4,
+            TypeSpec::BPF => 4. * (y2 - 2. * y3 + y4),
+            TypeSpec::Notch => u - 4. * y1 + 6. * y2 - 4. * y3 + 2. * y4,
+            // Lowpass, the other types are rejected by `Filter::new`
+            _ => y4,
+        }
+    }
+
+    fn view(&self) -> View {
+        View {
+            cutoff: self.cutoff.normalized(),
+            resonance: self.qfactor.normalized(),
+            key_tracking: self.key_tracking,
+            velocity_sensitivity: self.velocity_sensitivity,
+            // Unity, none of the types has a gain
+            gain: 0.5,
+            filter_type: self.filter_type,
+            model: Model::Ladder { drive: self.drive },
+        }
+    }
+}
+
+impl Modulated<ModTarget> for Ladder {
+    fn mod_param(&mut self, target: ModTarget) -> Option<&mut ModParam> {
+        match target {
+            ModTarget::Cutoff => Some(&mut self.cutoff),
+            ModTarget::QFactor => Some(&mut self.qfactor),
+            ModTarget::Gain => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: Hz = 44100.;
+
+    fn ladder(resonance: Proportion, drive: Proportion) -> Ladder {
+        let specs = Specs { model: Model::Ladder { drive }, filter_type: TypeSpec::LPF, cutoff: 1000. / MAX_CUTOFF,
+                            resonance, ..Default::default() };
+        Ladder::new(SAMPLE_RATE, specs, drive)
+    }
+
+    fn peak(filter: &Ladder, input: impl Fn(usize) -> Sample) -> Sample {
+        let mut state = [0.; 4];
+        (0..44100).map(|i| filter.filter(&mut state, input(i), CutoffMod::default()).abs())
+            .skip(22050)
+            .fold(0., f64::max)
+    }
+
+    fn sine(freq: Hz) -> impl Fn(usize) -> Sample {
+        move |i| (2. * PI * freq * i as f64 / SAMPLE_RATE).sin() * 0.1
+    }
+
+    #[test]
+    fn four_poles() {
+        let filter = ladder(0., 0.);
+        assert!(peak(&filter, sine(100.)) > 0.09);
+        assert!(peak(&filter, sine(10000.)) < 0.1 * 1e-3);
+    }
+
+    #[test]
+    fn self_oscillates_at_full_resonance() {
+        let impulse = |i| if i == 0 { 1. } else { 0. };
+        assert!(peak(&ladder(1., 0.), impulse) > 0.1);
+        assert!(peak(&ladder(0.5, 0.), impulse) < 1e-6);
+    }
+
+    #[test]
+    fn drive_stays_bounded() {
+        let loud = |i| if i % 100 < 50 { 10. } else { -10. };
+        assert!(peak(&ladder(1., 1.), loud) < 2.);
+    }
+
+    #[test]
+    fn rejects_eq_types() {
+        let specs = |filter_type| Specs { model: Model::Ladder { drive: 0. }, filter_type, ..Default::default() };
+        assert!(<dyn Filter>::new(specs(TypeSpec::Notch), SAMPLE_RATE).is_ok());
+        for filter_type in [TypeSpec::LowShelf, TypeSpec::HighShelf, TypeSpec::Peaking, TypeSpec::AllPass] {
+            assert!(<dyn Filter>::new(specs(filter_type), SAMPLE_RATE).is_err(), "{:?}", filter_type);
+        }
+    }
+}
@@ -1,53 +1,120 @@
 mod biquad;
+mod state_variable;
+mod ladder;
 
-use super::{Sample, modulated::*};
+use super::{Sample, Frame, Proportion, modulated::*};
 use crate::core::music_theory::Hz;
 
 const MAX_CUTOFF: Hz = 440. * 32.;
 const MIN_CUTOFF: Hz = 0.;
 const MAX_QFACTOR: f64 = 50.;
 const MIN_QFACTOR: f64 = 1.;
+/// Boost or cut of the shelving and peaking types
+const MAX_GAIN_DB: f64 = 24.;
+/// Highest cutoff relative to the sample rate, just below Nyquist where the filters blow up
+const MAX_RELATIVE_CUTOFF: f64 = 0.49;
 
+///
+/// Like oscillators, filters hold the parameters while each user, a voice or the whole mix,
+/// owns a `state` of `state_size` values with the signal history.
+///
 pub trait Filter: Modulated<ModTarget> {
-    fn filter(&mut self, input: Sample) -> Sample;
+    fn state_size(&self) -> usize;
+    fn filter(&self, state: &mut [f64], input: Sample, cutoff_mod: CutoffMod) -> Sample;
+    /// Each side is filtered with its own half of `state`, which is twice `state_size`
+    fn filter_frame(&self, state: &mut [f64], input: Frame, cutoff_mod: CutoffMod) -> Frame {
+        let (left, right) = state.split_at_mut(self.state_size());
+        Frame::new(self.filter(left, input.left, cutoff_mod), self.filter(right, input.right, cutoff_mod))
+    }
     fn view(&self) -> View;
 }
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Specs {
+    pub model: Model,
     pub filter_type: TypeSpec,
     pub cutoff: f64,
     pub resonance: f64,
+    /// Only for shelving and peaking, 0.5 is unity
+    pub gain: f64,
+    /// How much the cutoff follows the note, 1 keeps the same ratio to the note's frequency.
+    /// Like velocity sensitivity, filters each voice on its own even in global mode.
+    pub key_tracking: Proportion,
+    /// How much softer notes close the cutoff
+    pub velocity_sensitivity: Proportion,
+}
+
+/// Per voice changes to the cutoff
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CutoffMod {
+    /// Added to the normalized cutoff, e.g. by an envelope
+    pub shift: Proportion,
+    /// Then multiplies the cutoff frequency, e.g. by key tracking
+    pub ratio: f64,
+}
+
+/// Cutoff frequency after per voice modulation
+fn cutoff(param: &ModParam, modulation: CutoffMod, sample_rate: Hz) -> Hz {
+    (param.calculate_shifted(modulation.shift) * modulation.ratio).min(sample_rate * MAX_RELATIVE_CUTOFF)
 }
 
 #[derive(Clone, Copy, PartialEq, Debug)]
-pub enum TypeSpec { LPF, HPF, BPF, Notch }
+pub enum TypeSpec { LPF, HPF, BPF, Notch, LowShelf, HighShelf, Peaking, AllPass }
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Model {
+    Biquad,
+    /// Topology preserving state variable filter, stays stable when the cutoff moves fast
+    StateVariable,
+    /// 4 pole Moog style, self oscillating at full resonance. `drive` saturates the input.
+    /// Shelving, peaking and all-pass types aren't supported.
+    Ladder { drive: Proportion },
+}
 
 #[derive(Copy, Clone, PartialEq, Debug)]
-pub enum ModTarget { Cutoff, QFactor }
+pub enum ModTarget { Cutoff, QFactor, Gain }
 
 impl dyn Filter {
-    pub fn new(specs: Specs, sample_rate: Hz) -> Box<dyn Filter> {
-        let filter = biquad::BiquadFilter::new(sample_rate, specs);
-        Box::new(filter)
+    pub fn new(specs: Specs, sample_rate: Hz) -> Result<Box<dyn Filter>, String> {
+        match (specs.model, specs.filter_type) {
+            (Model::Ladder { .. }, TypeSpec::LowShelf | TypeSpec::HighShelf | TypeSpec::Peaking | TypeSpec::AllPass) =>
+                Err(format!("Ladder filter has no {:?} type", specs.filter_type)),
+            (Model::Ladder { drive }, _) => Ok(Box::new(ladder::Ladder::new(sample_rate, specs, drive))),
+            (Model::Biquad, _) => Ok(Box::new(biquad::BiquadFilter::new(sample_rate, specs))),
+            (Model::StateVariable, _) => Ok(Box::new(state_variable::StateVariable::new(sample_rate, specs))),
+        }
     }
 }
 
 impl Default for Specs {
     fn default() -> Self {
         Specs {
+            model: Model::Biquad,
             filter_type: TypeSpec::LPF,
             cutoff: 1.,
             resonance: 0.05,
+            gain: 0.5,
+            key_tracking: 0.,
+            velocity_sensitivity: 0.,
         }
     }
 }
 
+impl Default for CutoffMod {
+    fn default() -> Self {
+        CutoffMod { shift: 0., ratio: 1. }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub struct View { //TODO remove if identical to Specs?
     pub cutoff: f64,
     pub resonance: f64,
+    pub gain: f64,
+    pub key_tracking: Proportion,
+    pub velocity_sensitivity: Proportion,
     pub filter_type: TypeSpec,
+    pub model: Model,
 }
 
 impl Default for View {
@@ -55,7 +122,11 @@ impl Default for View {
         View {
             cutoff: 1.,
             resonance: 0.,
-            filter_type: TypeSpec::LPF
+            gain: 0.5,
+            key_tracking: 0.,
+            velocity_sensitivity: 0.,
+            filter_type: TypeSpec::LPF,
+            model: Model::Biquad,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,160 @@
+use super::*;
+use std::f64::consts::PI;
+
+
+/// Trapezoidal integrated SVF, after Andrew Simper's "Linear Trapezoidal Integrated SVF".
+/// All responses come out of the same two integrators, so the type mostly picks how to mix them.
+/// https://cytomic.com/files/dsp/SvfLinearTrapOptimised2.pdf
+pub(super) struct StateVariable {
+    sample_rate: Hz,
+    cutoff: ModParam,
+    qfactor: ModParam,
+    gain: ModParam,
+    filter_type: TypeSpec,
+    key_tracking: Proportion,
+    velocity_sensitivity: Proportion,
+}
+
+struct Outputs {
+    low: Sample,
+    band: Sample,
+    high: Sample,
+}
+
+impl StateVariable {
+    pub(super) fn new(sample_rate: Hz, specs: Specs) -> StateVariable {
+        assert!(sample_rate > 0., "sample_rate was: {}", sample_rate);
+        StateVariable {
+            sample_rate,
+            filter_type: specs.filter_type,
+            cutoff: ModParam::with_base(specs.cutoff, MIN_CUTOFF, MAX_CUTOFF),
+            qfactor: ModParam::with_base(specs.resonance, MIN_QFACTOR, MAX_QFACTOR),
+            gain: ModParam::with_base(specs.gain, -MAX_GAIN_DB, MAX_GAIN_DB),
+            key_tracking: specs.key_tracking,
+            velocity_sensitivity: specs.velocity_sensitivity,
+        }
+    }
+
+    /// `state` holds the two integrators
+    fn outputs(&self, state: &mut [f64], input: Sample, g: f64, k: f64) -> Outputs {
+        let a1 = 1. / (1. + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+
+        let v3 = input - state[1];
+        let v1 = a1 * state[0] + a2 * v3;
+        let v2 = state[1] + a2 * state[0] + a3 * v3;
+        state[0] = 2. * v1 - state[0];
+        state[1] = 2. * v2 - state[1];
+
+        Outputs { low: v2, band: v1, high: input - k * v1 - v2 }
+    }
+}
+
+impl Filter for StateVariable {
+    fn state_size(&self) -> usize { 2 }
+
+    fn filter(&self, state: &mut [f64], input: Sample, cutoff_mod: CutoffMod) -> Sample {
+        let cutoff = cutoff(&self.cutoff, cutoff_mod, self.sample_rate);
+        let g = (PI * cutoff / self.sample_rate).tan();
+        let k = 1. / self.qfactor.calculate();
+        let a = 10_f64.powf(self.gain.calculate() / 40.);
+        match self.filter_type {
+            TypeSpec::LPF => self.outputs(state, input, g, k).low,
+            TypeSpec::HPF => self.outputs(state, input, g, k).high,
+            TypeSpec::BPF => self.outputs(state, input, g, k).band,
+            TypeSpec::Notch => {
+                let o = self.outputs(state, input, g, k);
+                o.low + o.high
+            },
+            TypeSpec::AllPass => input - 2. * k * self.outputs(state, input, g, k).band,
+            TypeSpec::Peaking => {
+                let k = k / a;
+                input + k * (a * a - 1.) * self.outputs(state, input, g, k).band
+            },
+            TypeSpec::LowShelf => {
+                let o = self.outputs(state, input, g / a.sqrt(), k);
+                input + k * (a - 1.) * o.band + (a * a - 1.) * o.low
+            },
+            TypeSpec::HighShelf => {
+                let o = self.outputs(state, input, g * a.sqrt(), k);
+                a * a * input + k * (1. - a) * a * o.band + (1. - a * a) * o.low
+            },
+        }
+    }
+
+    fn view(&self) -> View {
+        View {
+            cutoff: self.cutoff.normalized(),
+            resonance: self.qfactor.normalized(),
+            key_tracking: self.key_tracking,
+            velocity_sensitivity: self.velocity_sensitivity,
+            gain: self.gain.normalized(),
+            filter_type: self.filter_type,
+            model: Model::StateVariable,
+        }
+    }
+}
+
+impl Modulated<ModTarget> for StateVariable {
+    fn mod_param(&mut self, target: ModTarget) -> Option<&mut ModParam> {
+        match target {
+            ModTarget::Cutoff => Some(&mut self.cutoff),
+            ModTarget::QFactor => Some(&mut self.qfactor),
+            ModTarget::Gain => Some(&mut self.gain),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: Hz = 44100.;
+
+    fn filter(filter_type: TypeSpec) -> StateVariable {
+        let specs = Specs { model: Model::StateVariable, filter_type, cutoff: 1000. / MAX_CUTOFF,
+                            resonance: 0., gain: 1., ..Default::default() };
+        StateVariable::new(SAMPLE_RATE, specs)
+    }
+
+    fn peak(filter: &StateVariable, freq: Hz) -> Sample {
+        let mut state = [0.; 2];
+        (0..4410).map(|i| (2. * PI * freq * i as f64 / SAMPLE_RATE).sin())
+            .map(|input| filter.filter(&mut state, input, CutoffMod::default()).abs())
+            .skip(2205)
+            .fold(0., f64::max)
+    }
+
+    #[test]
+    fn responses() {
+        let (low, high) = (filter(TypeSpec::LPF), filter(TypeSpec::HPF));
+        assert!(peak(&low, 100.) > 0.9 && peak(&low, 10000.) < 0.05);
+        assert!(peak(&high, 100.) < 0.05 && peak(&high, 10000.) > 0.9);
+        assert!(peak(&filter(TypeSpec::Notch), 1000.) < 0.01);
+        assert!(peak(&filter(TypeSpec::BPF), 1000.) > 0.9);
+    }
+
+    #[test]
+    fn eq_responses() {
+        let full_gain = 10_f64.powf(MAX_GAIN_DB / 20.);
+        assert!((peak(&filter(TypeSpec::Peaking), 1000.) - full_gain).abs() < 0.1);
+        assert!((peak(&filter(TypeSpec::Peaking), 20.) - 1.).abs() < 0.05);
+        assert!((peak(&filter(TypeSpec::LowShelf), 20.) - full_gain).abs() < 0.5);
+        assert!((peak(&filter(TypeSpec::HighShelf), 20.) - 1.).abs() < 0.05);
+        assert!((peak(&filter(TypeSpec::AllPass), 3000.) - 1.).abs() < 0.01);
+    }
+
+    #[test]
+    fn stable_under_fast_modulation() {
+        let mut filter = filter(TypeSpec::LPF);
+        filter.mod_param(ModTarget::QFactor).unwrap().set_base(1.);
+        let mut state = [0.; 2];
+        for i in 0..44100 {
+            let jumping_cutoff = if i % 2 == 0 { 0. } else { 1. };
+            filter.mod_param(ModTarget::Cutoff).unwrap().set_base(jumping_cutoff);
+            let output = filter.filter(&mut state, if i % 100 < 50 { 1. } else { -1. }, CutoffMod::default());
+            assert!(output.abs() < 100., "{}: {}", i, output);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::{iter::Sum, ops::{Add, AddAssign, Mul}};
+use super::{Sample, Pan};
+
+/// A stereo sample
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct Frame {
+    pub left: Sample,
+    pub right: Sample,
+}
+
+impl Frame {
+    pub fn new(left: Sample, right: Sample) -> Frame {
+        Frame { left, right }
+    }
+
+    /// The same sample on both sides
+    pub fn mono(sample: Sample) -> Frame {
+        Frame { left: sample, right: sample }
+    }
+
+    /// Mixed down to a single channel
+    pub fn to_mono(self) -> Sample {
+        (self.left + self.right) / 2.
+    }
+
+    /// Turns down the opposite side, leaving the center untouched
+    pub fn balance(self, pan: Pan) -> Frame {
+        let pan = pan.clamp(-1., 1.);
+        Frame { left: self.left * (1. - pan).min(1.), right: self.right * (1. + pan).min(1.) }
+    }
+
+    pub fn map(self, f: impl Fn(Sample) -> Sample) -> Frame {
+        Frame { left: f(self.left), right: f(self.right) }
+    }
+}
+
+impl Add for Frame {
+    type Output = Frame;
+    fn add(self, other: Frame) -> Frame {
+        Frame { left: self.left + other.left, right: self.right + other.right }
+    }
+}
+
+impl AddAssign for Frame {
+    fn add_assign(&mut self, other: Frame) {
+        *self = *self + other;
+    }
+}
+
+impl Mul<f64> for Frame {
+    type Output = Frame;
+    fn mul(self, scale: f64) -> Frame {
+        self.map(|sample| sample * scale)
+    }
+}
+
+impl Sum for Frame {
+    fn sum<I: Iterator<Item=Frame>>(iter: I) -> Frame {
+        iter.fold(Frame::default(), Add::add)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balance() {
+        let frame = Frame::mono(0.8);
+        assert_eq!(frame.balance(0.), frame);
+        assert_eq!(frame.balance(-1.), Frame::new(0.8, 0.));
+        assert_eq!(frame.balance(0.5), Frame::new(0.4, 0.8));
+        assert_eq!(frame.balance(2.), Frame::new(0., 0.8));
+    }
+}
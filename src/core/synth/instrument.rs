@@ -1,6 +1,25 @@
-use super::{Sample, Seconds, Proportion, Velocity, oscillator::{self, Oscillator},
-            filter::{self, Filter}, adsr::Adsr, lfo::{self, LFO}, modulated::*};
-use crate::core::music_theory::{Hz, pitch::Pitch};
+use super::{Frame, Seconds, Proportion, Velocity, Pan, oscillator, section::{self, Section},
+            filter::{self, Filter, CutoffMod}, adsr::{Adsr, Dahdsr}, lfo::{self, LFO}, modulated::*};
+use crate::core::music_theory::{Hz, Semitones, pitch::Pitch};
+
+/// How far the pitch can be modulated, e.g. by an LFO for vibrato, in cents
+const MAX_PITCH_MOD: f64 = 100.;
+/// Notes above it open the cutoff when the filter tracks the keyboard. Middle C.
+const KEY_TRACKING_CENTER: Hz = 261.63;
+/// How far the cutoff closes for the softest note at full velocity sensitivity
+const VELOCITY_OCTAVES: f64 = 4.;
+/// Fade out of a stolen voice, short enough to be heard as a retrigger but without clicking
+const STEAL_FADE: Seconds = 0.005;
+/// Longest attack through modulation, relative to the envelope's
+const MAX_ATTACK_SCALE: f64 = 2.;
+/// Loudness range of the exponential velocity curve, from the softest to the hardest note
+const VELOCITY_RANGE_DB: f64 = 40.;
+/// Highest MIDI note, for the key modulation source
+const MAX_KEY: f64 = 127.;
+/// Short enough to follow the XY pad closely, long enough to avoid zipper noise
+pub(super) const DEFAULT_SMOOTHING: Seconds = 0.01;
+/// Notes held at once in mono and legato modes before the stack has to grow
+const NOTE_STACK_CAPACITY: usize = 16;
 
 ///
 /// Connects modules of the synthesizer together to produce a stream of sound samples.
@@ -9,76 +28,263 @@ use crate::core::music_theory::{Hz, pitch::Pitch};
 #[derive(Clone, PartialEq, Debug)]
 pub struct Specs {
     pub max_voices: u8,
-    pub oscillator: oscillator::Specs,
+    pub voice_mode: VoiceMode,
+    /// Which voice makes room for a new note once all are playing, in poly mode
+    pub voice_stealing: VoiceStealing,
+    pub velocity_curve: VelocityCurve,
+    pub oscillators: section::Specs,
     pub filter: filter::Specs,
-    pub lfo: Option<lfo::Specs>,
+    pub filter_mode: FilterMode,
+    pub lfos: Vec<lfo::Specs>,
+    /// Envelopes that only modulate, one per voice
+    pub mod_envelopes: Vec<Dahdsr>,
+    pub mod_matrix: Vec<ModSlot>,
     pub adsr: Adsr,
     pub volume: Proportion,
-    pub modulation_x: ModTarget,
-    pub modulation_y: ModTarget,
-    pub modulation_lfo: ModSpecs,
+    pub pan: Pan,
+    /// Time to slide from the previous note's frequency, 0 to disable
+    pub glide: Seconds,
+    /// Pitch change at full pitch bend
+    pub bend_range: Semitones,
+    /// Time constant for changes to modulated parameters, 0 to apply them right away
+    pub smoothing: Seconds,
+}
+
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub enum VoiceMode {
+    #[default]
+    Poly,
+    /// A single voice playing the last held note, going back to the previous one on release
+    Mono,
+    /// Like mono, but changing notes while holding doesn't restart the envelope
+    Legato,
+}
+
+/// Applied to the velocity of each note, before it reaches the envelope and modulation
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub enum VelocityCurve {
+    #[default]
+    Linear,
+    /// Equal steps in velocity make equal steps in loudness, over `VELOCITY_RANGE_DB`
+    Exponential,
+    /// Every note plays at the same velocity
+    Fixed(Velocity),
+}
+
+impl VelocityCurve {
+    pub fn apply(&self, velocity: Velocity) -> Velocity {
+        match self {
+            VelocityCurve::Linear => velocity,
+            VelocityCurve::Exponential if velocity > 0. => 10_f64.powf(VELOCITY_RANGE_DB * (velocity - 1.) / 20.),
+            VelocityCurve::Exponential => 0.,
+            VelocityCurve::Fixed(fixed) => *fixed,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub enum FilterMode {
+    /// One filter for the mix of all voices, cheaper.
+    /// Key tracking, velocity sensitivity and voice sources in the matrix targeting the filter,
+    /// volume or pan still get one per voice.
+    #[default]
+    Global,
+    /// Each voice has its own filter, with an envelope shifting the cutoff by up to `amount`
+    PerVoice { envelope: Adsr, amount: Proportion },
+}
+
+/// Released voices are stolen first, then the policy decides among the rest
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub enum VoiceStealing {
+    #[default]
+    Oldest,
+    Quietest,
+    Lowest,
+    Highest,
+    /// Restarts the voice already playing the same pitch, otherwise the oldest
+    SamePitch,
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum ModTarget {
-    Noop, Volume,
+    Noop, Volume, Pitch, Pan,
+    /// Scales the attack time of the amplitude envelope, from none to twice as long
+    Attack,
     Filter(filter::ModTarget),
     Oscillator(oscillator::ModTarget),
 }
 
+///
+/// Sources from 0 to 1. Velocity, key and envelopes are per voice, so they only reach
+/// what is computed for each voice: oscillators, pitch and per voice filters.
+///
 #[derive(Copy, Clone, PartialEq, Debug)]
-pub struct ModSpecs {
+pub enum ModSource {
+    /// Index in `Specs::lfos`
+    Lfo(usize),
+    /// Index in `Specs::mod_envelopes`
+    Envelope(usize),
+    Velocity,
+    Key,
+    ModWheel,
+    Aftertouch,
+    /// The XY pad sets the base of its targets rather than modulating around it
+    X, Y,
+}
+impl ModSource {
+    /// Takes a different value for each voice
+    fn is_per_voice(self) -> bool {
+        matches!(self, ModSource::Envelope(_) | ModSource::Velocity | ModSource::Key)
+    }
+}
+
+/// Contributions of all slots with the same target add up
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ModSlot {
+    pub source: ModSource,
     pub target: ModTarget,
-    pub amount: Proportion,
+    /// From -1 to 1, negative moves the target down
+    pub amount: f64,
+    pub polarity: Polarity,
+}
+
+impl ModSlot {
+    /// LFOs swing around the base of the target, other sources only push it one way
+    pub fn new(source: ModSource, target: ModTarget, amount: f64) -> ModSlot {
+        let polarity = match source {
+            ModSource::Lfo(_) => Polarity::Bipolar,
+            _ => Polarity::Unipolar,
+        };
+        ModSlot { source, target, amount: amount.clamp(-1., 1.), polarity }
+    }
+
+    /// E.g. bipolar velocity makes soft notes quieter and hard ones louder than the base
+    pub fn with_polarity(self, polarity: Polarity) -> ModSlot {
+        ModSlot { polarity, ..self }
+    }
 }
 
 #[derive(Clone, PartialEq, Default, Debug)]
 pub struct View {
-    pub oscillator: oscillator::View,
+    pub oscillators: section::View,
     pub filter: filter::View,
-    pub lfo: Option<lfo::View>,
+    pub filter_mode: FilterMode,
+    pub lfos: Vec<lfo::View>,
+    pub mod_envelopes: Vec<Dahdsr>,
+    pub mod_matrix: Vec<ModSlot>,
     pub adsr: Adsr,
     pub volume: Proportion,
+    pub pan: Pan,
+    pub voice_mode: VoiceMode,
+    pub voice_stealing: VoiceStealing,
+    pub velocity_curve: VelocityCurve,
+    pub active_voices: usize,
+    pub max_voices: u8,
+    pub glide: Seconds,
+    pub bend_range: Semitones,
+    pub smoothing: Seconds,
 }
 
 #[derive(Clone)]
 pub struct State {
     voices: Voices,
-    clock: Clock,
+    lfos: Vec<lfo::State>,
+    beat: Seconds,
 }
 
 pub struct Instrument {
-    oscillator: Box<dyn Oscillator>,
+    oscillators: Section,
     filter: Box<dyn Filter>,
-    lfo: Option<LFO>,
+    filter_mode: FilterMode,
+    /// Filter, volume and pan run once per voice, in per-voice filter mode or when voice sources modulate them
+    voice_chain: bool,
+    key_tracking: Proportion,
+    velocity_sensitivity: Proportion,
+    /// Used in global filter mode
+    filter_state: Vec<f64>,
+    lfos: Vec<LFO>,
+    /// Output of each LFO at the current sample, from 0 to 1
+    lfo_values: Vec<f64>,
+    mod_envelopes: Vec<Dahdsr>,
+    mod_matrix: Vec<ModSlot>,
+    /// Targets of the matrix without repetition
+    mod_targets: Vec<ModTarget>,
+    mod_wheel: Proportion,
+    aftertouch: Proportion,
     adsr: Adsr,
+    attack: ModParam,
+    velocity_curve: VelocityCurve,
     volume: ModParam,
-    modulation_x: ModTarget,
-    modulation_y: ModTarget,
-    modulation_lfo: ModSpecs,
+    pan: ModParam,
+    pitch: ModParam,
+    bend_range: Semitones,
+    bend: Proportion,
+    smoothing: Seconds,
+    /// Duration of a quarter note, for tempo synced LFOs
+    beat: Seconds,
     voices: Voices,
-    clock: Clock,
 }
 
 impl Instrument {
 
-    pub fn new(specs: Specs, sample_rate: Hz) -> Instrument {
-        Instrument {
-            oscillator: <dyn Oscillator>::new(&specs.oscillator),
-            filter: <dyn Filter>::new(specs.filter, sample_rate),
-            lfo: specs.lfo.map(LFO::new),
+    pub fn new(specs: Specs, sample_rate: Hz) -> Result<Instrument, String> {
+        let oscillators = Section::new(&specs.oscillators, sample_rate)?;
+        let filter = <dyn Filter>::new(specs.filter, sample_rate)?;
+        let filter_state = vec![0.; 2 * filter.state_size()];
+        let voice_chain = matches!(specs.filter_mode, FilterMode::PerVoice { .. }) ||
+            specs.filter.key_tracking != 0. || specs.filter.velocity_sensitivity != 0. ||
+            specs.mod_matrix.iter().any(|slot| slot.source.is_per_voice() && matches!(slot.target,
+                ModTarget::Filter(_) | ModTarget::Volume | ModTarget::Pan));
+        let voice_filter_state_size = if voice_chain { filter_state.len() } else { 0 };
+        let voices = Voices::new(&specs, sample_rate, oscillators.state_size(), voice_filter_state_size);
+        let mut mod_targets: Vec<ModTarget> = vec![];
+        for slot in specs.mod_matrix.iter() {
+            if !mod_targets.contains(&slot.target) {
+                mod_targets.push(slot.target);
+            }
+        }
+        let mut instrument = Instrument {
+            oscillators,
+            filter,
+            filter_mode: specs.filter_mode,
+            voice_chain,
+            key_tracking: specs.filter.key_tracking,
+            velocity_sensitivity: specs.filter.velocity_sensitivity,
+            filter_state,
+            lfo_values: vec![0.; specs.lfos.len()],
+            lfos: specs.lfos.into_iter().map(|lfo| LFO::new(lfo, sample_rate)).collect(),
+            mod_envelopes: specs.mod_envelopes,
+            mod_matrix: specs.mod_matrix,
+            mod_targets,
+            mod_wheel: 0.,
+            aftertouch: 0.,
             adsr: specs.adsr,
+            attack: ModParam::with_base(1. / MAX_ATTACK_SCALE, 0., MAX_ATTACK_SCALE),
+            velocity_curve: specs.velocity_curve,
             volume: ModParam::with_base(specs.volume, 0., 1.),
-            modulation_x: specs.modulation_x,
-            modulation_y: specs.modulation_y,
-            modulation_lfo: specs.modulation_lfo,
-            clock: Clock::new(sample_rate),
-            voices: Voices::new(specs.max_voices, sample_rate, specs.adsr.release),
+            pan: ModParam::with_base((specs.pan + 1.) / 2., -1., 1.),
+            pitch: ModParam::with_base(0.5, -MAX_PITCH_MOD, MAX_PITCH_MOD),
+            bend_range: specs.bend_range,
+            bend: 0.,
+            smoothing: specs.smoothing,
+            beat: lfo::DEFAULT_BEAT,
+            voices,
+        };
+        for i in 0..instrument.mod_targets.len() {
+            let target = instrument.mod_targets[i];
+            if let Some(param) = instrument.mod_param(target) {
+                param.set_smoothing(specs.smoothing, sample_rate);
+            }
         }
+        Ok(instrument)
     }
 
     pub fn hold(&mut self, pitch: Pitch, velocity: Velocity) {
-        self.voices.hold(pitch, velocity)
+        let velocity = self.velocity_curve.apply(velocity);
+        let started = self.voices.hold(pitch, velocity);
+        if started {
+            self.lfos.iter_mut().for_each(LFO::trigger);
+        }
     }
 
     pub fn release(&mut self, pitch: Pitch) {
@@ -89,48 +295,167 @@ impl Instrument {
         self.voices.release_all()
     }
 
-    pub fn next_sample(&mut self) -> Sample {
-        self.run_next_lfo_modulation();
-        let oscillator = &self.oscillator;
-        let adsr = &self.adsr;
+    /// From -1 to 1, scaled by the bend range
+    pub fn set_pitch_bend(&mut self, amount: Proportion) {
+        self.bend = amount.clamp(-1., 1.);
+    }
+
+    /// Ignores non-positive beats, e.g. a zero tempo from a MIDI file
+    pub fn set_tempo(&mut self, beat: Seconds) {
+        if beat <= 0. {
+            return;
+        }
+        self.beat = beat;
+        self.lfos.iter_mut().for_each(|lfo| lfo.set_beat(beat));
+    }
+
+    /// From -1, left, to 1, right
+    pub fn set_pan(&mut self, pan: Pan) {
+        self.pan.set_base((pan + 1.) / 2.);
+    }
+
+    pub fn set_mod_wheel(&mut self, value: Proportion) {
+        self.mod_wheel = value.clamp(0., 1.);
+    }
+
+    pub fn set_aftertouch(&mut self, value: Proportion) {
+        self.aftertouch = value.clamp(0., 1.);
+    }
+
+    /// Fills `out` with the next frames
+    pub fn process(&mut self, out: &mut [Frame]) {
+        out.iter_mut().for_each(|frame| *frame = self.next_sample());
+    }
+
+    pub fn next_sample(&mut self) -> Frame {
+        self.run_global_modulation();
         self.voices.drop_finished_voices();
-        let sample_mix: Sample = self.voices.voices.iter_mut()
-            .map(|voice| Instrument::next_sample_for_voice(voice, oscillator, adsr))
+        let mut voices = std::mem::take(&mut self.voices.voices);
+        let mix: Frame = voices.iter_mut()
+            .map(|voice| self.next_sample_for_voice(voice))
             .sum();
-        let sample_filtered = self.filter.filter(sample_mix);
-        sample_filtered * self.volume.calculate()
+        self.voices.voices = voices;
+        self.clear_voice_modulation();
+        if self.voice_chain {
+            mix
+        } else {
+            let filtered = self.filter.filter_frame(&mut self.filter_state, mix, CutoffMod::default());
+            self.output(filtered)
+        }
     }
 
-    fn next_sample_for_voice(voice: &mut Voice, oscillator: &Box<dyn Oscillator>, adsr: &Adsr) -> Sample {
-        let clock = voice.clock.tick();
-        let sample = oscillator.next_sample(clock, voice.pitch.freq(), 0.) * voice.velocity;
-        adsr.apply(voice.clock(), voice.released_clock().unwrap_or(0.), sample)
+    fn output(&self, frame: Frame) -> Frame {
+        (frame * self.volume.calculate()).balance(self.pan.calculate())
+    }
+
+    fn next_sample_for_voice(&mut self, voice: &mut Voice) -> Frame {
+        voice.clock.tick();
+        self.run_voice_modulation(voice);
+        let freq = voice.freq(self.voices.glide) * self.pitch_offset();
+        let frame = self.oscillators.next_sample(&mut voice.oscillator_state, freq, 0.);
+        let (elapsed, since_release) = (voice.clock(), voice.released_clock().unwrap_or(0.));
+        let adsr = Adsr { attack: self.adsr.attack * self.attack.calculate(), ..self.adsr };
+        voice.level = adsr.apply(elapsed, since_release, voice.velocity) * voice.fade();
+        if !self.voice_chain {
+            return frame * voice.level;
+        }
+        let cutoff_mod = match self.filter_mode {
+            FilterMode::Global => CutoffMod { shift: 0., ratio: self.cutoff_ratio(freq, voice.velocity) },
+            FilterMode::PerVoice { envelope, amount } => CutoffMod {
+                shift: envelope.apply(elapsed, since_release, amount),
+                ratio: self.cutoff_ratio(freq, voice.velocity),
+            },
+        };
+        let filtered = self.filter.filter_frame(&mut voice.filter_state, frame, cutoff_mod);
+        self.output(filtered * voice.level)
+    }
+
+    fn voice_filter_state_size(&self) -> usize {
+        if self.voice_chain { 2 * self.filter.state_size() } else { 0 }
+    }
+
+    /// Frequency ratio from pitch bend and pitch modulation
+    fn pitch_offset(&self) -> f64 {
+        let cents = self.bend * f64::from(self.bend_range) * 100. + self.pitch.calculate();
+        2_f64.powf(cents / 1200.)
+    }
+
+    /// From key tracking and velocity sensitivity
+    fn cutoff_ratio(&self, freq: Hz, velocity: Velocity) -> f64 {
+        let key_ratio = (freq / KEY_TRACKING_CENTER).powf(self.key_tracking);
+        let velocity_octaves = VELOCITY_OCTAVES * self.velocity_sensitivity * (velocity - 1.);
+        key_ratio * 2_f64.powf(velocity_octaves)
     }
 
     pub fn set_xy_params(&mut self, x: f64, y: f64) {
-        let x_target = self.modulation_x;
-        let y_target = self.modulation_y;
-        if let Some(param) = self.mod_param(x_target){
-            param.set_base(x);
+        for i in 0..self.mod_matrix.len() {
+            let slot = self.mod_matrix[i];
+            let value = match slot.source {
+                ModSource::X => x,
+                ModSource::Y => y,
+                _ => continue,
+            };
+            if let Some(param) = self.mod_param(slot.target) {
+                param.set_base(value * slot.amount);
+            }
+        }
+    }
+
+    /// Sources shared by all voices, summed into the targets until the next sample
+    fn run_global_modulation(&mut self) {
+        for (lfo, value) in self.lfos.iter_mut().zip(self.lfo_values.iter_mut()) {
+            *value = (lfo.next_sample() + 1.) / 2.;
+        }
+        for i in 0..self.mod_targets.len() {
+            let target = self.mod_targets[i];
+            if let Some(param) = self.mod_param(target) {
+                param.clear_signal();
+            }
+        }
+        for i in 0..self.mod_matrix.len() {
+            let slot = self.mod_matrix[i];
+            let value = match slot.source {
+                ModSource::Lfo(lfo) => self.lfo_values.get(lfo).copied(),
+                ModSource::ModWheel => Some(self.mod_wheel),
+                ModSource::Aftertouch => Some(self.aftertouch),
+                _ => None,
+            };
+            if let (Some(value), Some(param)) = (value, self.mod_param(slot.target)) {
+                param.add_signal(slot.polarity.apply(value) * slot.amount);
+            }
         }
-        if let Some(param) = self.mod_param(y_target){
-            param.set_base(y);
+        for i in 0..self.mod_targets.len() {
+            let target = self.mod_targets[i];
+            if let Some(param) = self.mod_param(target) {
+                param.smooth();
+            }
         }
     }
 
-    fn run_next_lfo_modulation(&mut self) {
-        let maybe_lfo_sample = {
-            let clock_ref = &mut self.clock;
-            self.lfo.as_ref().map(|lfo| {
-                let clock = clock_ref.tick();
-                lfo.next(clock)
-            })
-        };
-        let specs = self.modulation_lfo;
-        if let Some(lfo_sample) = maybe_lfo_sample {
-            let normalized = (lfo_sample + 1.) / 2.;
-            if let Some(param) = self.mod_param(specs.target) {
-                param.set_signal(normalized * specs.amount);
+    /// Sources of one voice, summed into the targets while rendering it
+    fn run_voice_modulation(&mut self, voice: &Voice) {
+        self.clear_voice_modulation();
+        let (elapsed, since_release) = (voice.clock(), voice.released_clock().unwrap_or(0.));
+        for i in 0..self.mod_matrix.len() {
+            let slot = self.mod_matrix[i];
+            let value = match slot.source {
+                ModSource::Envelope(envelope) => self.mod_envelopes.get(envelope)
+                    .map(|envelope| envelope.apply(elapsed, since_release, 1.)),
+                ModSource::Velocity => Some(voice.velocity),
+                ModSource::Key => Some(voice.pitch.index() as f64 / MAX_KEY),
+                _ => None,
+            };
+            if let (Some(value), Some(param)) = (value, self.mod_param(slot.target)) {
+                param.add_voice_signal(slot.polarity.apply(value) * slot.amount);
+            }
+        }
+    }
+
+    fn clear_voice_modulation(&mut self) {
+        for i in 0..self.mod_targets.len() {
+            let target = self.mod_targets[i];
+            if let Some(param) = self.mod_param(target) {
+                param.clear_voice_signal();
             }
         }
     }
@@ -138,22 +463,44 @@ impl Instrument {
     pub fn get_state(&self) -> State{
         State {
             voices: self.voices.clone(),
-            clock: self.clock.clone(),
+            lfos: self.lfos.iter().map(|lfo| lfo.state()).collect(),
+            beat: self.beat,
         }
     }
 
     pub fn set_state(&mut self, state: State) {
-        self.clock = state.clock;
-        self.voices = state.voices;
+        for (lfo, lfo_state) in self.lfos.iter_mut().zip(state.lfos) {
+            lfo.set_state(lfo_state);
+        }
+        self.set_tempo(state.beat);
+        self.voices.restore(state.voices);
+        let state_size = self.oscillators.state_size();
+        let filter_state_size = self.voice_filter_state_size();
+        self.voices.voices.iter_mut().for_each(|voice| {
+            voice.oscillator_state.resize(state_size, 0.);
+            voice.filter_state.resize(filter_state_size, 0.);
+        });
     }
 
     pub fn view(&self) -> View {
         View {
             filter: self.filter.view(),
-            oscillator: self.oscillator.view(),
-            lfo: self.lfo.as_ref().map(|l| l.view()),
+            filter_mode: self.filter_mode,
+            oscillators: self.oscillators.view(),
+            lfos: self.lfos.iter().map(|l| l.view()).collect(),
+            mod_envelopes: self.mod_envelopes.clone(),
+            mod_matrix: self.mod_matrix.clone(),
             adsr: self.adsr.clone(),
             volume: self.volume.normalized(),
+            pan: self.pan.calculate(),
+            voice_mode: self.voices.mode,
+            voice_stealing: self.voices.stealing,
+            velocity_curve: self.velocity_curve,
+            active_voices: self.voices.active_count(),
+            max_voices: self.voices.max_voices,
+            glide: self.voices.glide,
+            bend_range: self.bend_range,
+            smoothing: self.smoothing,
         }
     }
 }
@@ -163,8 +510,11 @@ impl Modulated<ModTarget> for Instrument {
         match target {
             ModTarget::Noop => None,
             ModTarget::Volume => Some(&mut self.volume),
+            ModTarget::Pan => Some(&mut self.pan),
+            ModTarget::Pitch => Some(&mut self.pitch),
+            ModTarget::Attack => Some(&mut self.attack),
             ModTarget::Filter(m) => self.filter.mod_param(m),
-            ModTarget::Oscillator(m) => self.oscillator.mod_param(m),
+            ModTarget::Oscillator(m) => self.oscillators.mod_param(m),
         }
     }
 }
@@ -172,43 +522,172 @@ impl Modulated<ModTarget> for Instrument {
 #[derive(Clone)]
 struct Voices {
     max_voices: u8,
+    mode: VoiceMode,
+    stealing: VoiceStealing,
+    glide: Seconds,
     voices: Vec<Voice>,
+    /// Finished voices kept to start new notes without allocating
+    pool: Vec<Voice>,
     sample_rate: Hz,
+    state_size: usize,
+    filter_state_size: usize,
     release: Seconds,
+    last_pitch: Option<Pitch>,
+    /// Notes held in mono and legato modes, the last one is playing
+    note_stack: Vec<(Pitch, Velocity)>,
 }
 impl Voices {
-    fn new(max_voices: u8, sample_rate: Hz, release: Seconds,) -> Voices {
-        Voices{ max_voices, voices: vec![], sample_rate, release }
+    fn new(specs: &Specs, sample_rate: Hz, state_size: usize, filter_state_size: usize) -> Voices {
+        // Stolen voices keep playing while they fade out
+        let capacity = 2 * usize::from(specs.max_voices.max(1));
+        let mut voices = Voices {
+            max_voices: specs.max_voices,
+            mode: specs.voice_mode,
+            stealing: specs.voice_stealing,
+            glide: specs.glide,
+            voices: Vec::with_capacity(capacity),
+            pool: Vec::with_capacity(capacity),
+            sample_rate, state_size, filter_state_size,
+            release: specs.adsr.release,
+            last_pitch: None,
+            note_stack: Vec::with_capacity(NOTE_STACK_CAPACITY),
+        };
+        voices.pool = (0..capacity).map(|_| voices.new_voice()).collect();
+        voices
     }
 
-    fn hold(&mut self, pitch: Pitch, velocity: Velocity) {
-        if self.has_free_voice() {
-            self.voices.push(Voice::new(self.sample_rate, pitch, velocity))
+    fn new_voice(&self) -> Voice {
+        let mut voice = Voice::new(self.sample_rate, Pitch::default(), 0., self.state_size);
+        voice.filter_state = vec![0.; self.filter_state_size];
+        voice
+    }
+
+    /// Whether the envelope starts over, rather than a legato note change
+    fn hold(&mut self, pitch: Pitch, velocity: Velocity) -> bool {
+        let started = match self.mode {
+            VoiceMode::Poly => {
+                if !self.has_free_voice() || self.stealing == VoiceStealing::SamePitch {
+                    self.steal_voice(pitch);
+                }
+                self.start_voice(pitch, velocity);
+                true
+            },
+            VoiceMode::Mono | VoiceMode::Legato => {
+                self.note_stack.retain(|(p, _)| *p != pitch);
+                self.note_stack.push((pitch, velocity));
+                let retrigger = self.mode == VoiceMode::Mono;
+                let glide = self.glide;
+                match self.voices.last_mut() {
+                    Some(voice) => {
+                        let restart = retrigger || !voice.is_holding();
+                        voice.change_note(pitch, velocity, glide, restart);
+                        restart
+                    },
+                    None => {
+                        self.start_voice(pitch, velocity);
+                        true
+                    },
+                }
+            },
+        };
+        self.last_pitch = Some(pitch);
+        started
+    }
+
+    fn start_voice(&mut self, pitch: Pitch, velocity: Velocity) {
+        let mut voice = self.pool.pop().unwrap_or_else(|| self.new_voice());
+        voice.start(pitch, velocity);
+        if self.glide > 0. {
+            voice.glide_from = self.last_pitch.map(Pitch::freq);
+        }
+        self.voices.push(voice);
+    }
+
+    /// Fades out the voice picked by the stealing policy, if any
+    fn steal_voice(&mut self, pitch: Pitch) {
+        let stealing = self.stealing;
+        let same_pitch = self.voices.iter()
+            .position(|v| !v.is_stolen() && stealing == VoiceStealing::SamePitch && v.pitch == pitch);
+        let victim = same_pitch.or_else(|| {
+            if self.has_free_voice() {
+                return None;
+            }
+            self.voices.iter().enumerate()
+                .filter(|(_, v)| !v.is_stolen())
+                .min_by(|(_, a), (_, b)| a.is_holding().cmp(&b.is_holding()).then_with(|| match stealing {
+                    VoiceStealing::Oldest | VoiceStealing::SamePitch => b.clock().total_cmp(&a.clock()),
+                    VoiceStealing::Quietest => a.level.total_cmp(&b.level),
+                    VoiceStealing::Lowest => a.pitch.index().cmp(&b.pitch.index()),
+                    VoiceStealing::Highest => b.pitch.index().cmp(&a.pitch.index()),
+                }))
+                .map(|(i, _)| i)
+        });
+        if let Some(i) = victim {
+            self.voices[i].steal();
         }
     }
 
     fn release(&mut self, pitch: Pitch) {
-        if let Some(voice) = self.find_holding_voice(pitch) {
-            voice.release();
+        match self.mode {
+            VoiceMode::Poly => if let Some(voice) = self.find_holding_voice(pitch) {
+                voice.release();
+            },
+            VoiceMode::Mono | VoiceMode::Legato => {
+                let was_playing = self.note_stack.last().map(|(p, _)| *p == pitch).unwrap_or(false);
+                self.note_stack.retain(|(p, _)| *p != pitch);
+                if !was_playing {
+                    return;
+                }
+                let retrigger = self.mode == VoiceMode::Mono;
+                let glide = self.glide;
+                match (self.note_stack.last().copied(), self.voices.last_mut()) {
+                    (Some((previous, velocity)), Some(voice)) => {
+                        voice.change_note(previous, velocity, glide, retrigger);
+                        self.last_pitch = Some(previous);
+                    },
+                    (None, Some(voice)) => voice.release(),
+                    _ => (),
+                }
+            },
         }
     }
 
     fn release_all(&mut self) {
+        self.note_stack.clear();
         self.voices.iter_mut().for_each(|v| v.release());
     }
 
+    /// Keeps playing the voices of a previous patch, with this patch's settings
+    fn restore(&mut self, previous: Voices) {
+        self.voices.extend(previous.voices);
+        self.last_pitch = previous.last_pitch;
+        self.note_stack.extend(previous.note_stack);
+    }
+
     fn find_holding_voice(&mut self, pitch: Pitch) -> Option<&mut Voice> {
         self.voices.iter_mut()
-            .find(|v| v.pitch == pitch && v.is_holding())
+            .find(|v| v.pitch == pitch && v.is_holding() && !v.is_stolen())
     }
 
+    /// Back to the pool, keeping the order of the others
     fn drop_finished_voices(&mut self) {
-        let release = self.release;
-        self.voices.retain(|voice| !voice.is_finished(release))
+        let mut i = 0;
+        while i < self.voices.len() {
+            if self.voices[i].is_finished(self.release) {
+                self.pool.push(self.voices.remove(i));
+            } else {
+                i += 1;
+            }
+        }
     }
 
     fn has_free_voice(&self) -> bool {
-        self.voices.len() < self.max_voices as usize
+        self.active_count() < self.max_voices as usize
+    }
+
+    /// Not counting voices fading out after being stolen
+    fn active_count(&self) -> usize {
+        self.voices.iter().filter(|v| !v.is_stolen()).count()
     }
 
 }
@@ -219,14 +698,64 @@ struct Voice {
     velocity: Velocity,
     released_at: Option<Seconds>,
     clock: Clock,
+    oscillator_state: Vec<f64>,
+    filter_state: Vec<f64>,
+    glide_from: Option<Hz>,
+    glide_start: Seconds,
+    stolen_at: Option<Seconds>,
+    /// Envelope times velocity at the last sample
+    level: Proportion,
 }
 impl Voice {
 
-    fn new(sample_rate: Hz, pitch: Pitch, velocity: Velocity) -> Voice {
+    fn new(sample_rate: Hz, pitch: Pitch, velocity: Velocity, state_size: usize) -> Voice {
         Voice {
             pitch, velocity,
             released_at: None,
-            clock: Clock::new(sample_rate)
+            clock: Clock::new(sample_rate),
+            oscillator_state: vec![0.; state_size],
+            filter_state: vec![],
+            glide_from: None,
+            glide_start: 0.,
+            stolen_at: None,
+            level: 0.,
+        }
+    }
+
+    /// Starts over as a new note, as if just created
+    fn start(&mut self, pitch: Pitch, velocity: Velocity) {
+        self.pitch = pitch;
+        self.velocity = velocity;
+        self.released_at = None;
+        self.clock.reset();
+        self.oscillator_state.iter_mut().for_each(|s| *s = 0.);
+        self.filter_state.iter_mut().for_each(|s| *s = 0.);
+        self.glide_from = None;
+        self.glide_start = 0.;
+        self.stolen_at = None;
+        self.level = 0.;
+    }
+
+    /// Plays another note keeping the oscillator phase. Gliding starts from the current frequency.
+    fn change_note(&mut self, pitch: Pitch, velocity: Velocity, glide: Seconds, retrigger: bool) {
+        let current_freq = self.freq(glide);
+        if retrigger {
+            self.clock.reset();
+            self.released_at = None;
+            self.velocity = velocity;
+        }
+        self.pitch = pitch;
+        self.glide_from = if glide > 0. { Some(current_freq) } else { None };
+        self.glide_start = self.clock();
+    }
+
+    /// Slides exponentially, i.e. at a constant rate in semitones
+    fn freq(&self, glide: Seconds) -> Hz {
+        let target = self.pitch.freq();
+        let elapsed = self.clock() - self.glide_start;
+        match self.glide_from {
+            Some(from) if elapsed < glide => from * (target / from).powf(elapsed / glide),
+            _ => target,
         }
     }
 
@@ -248,9 +777,22 @@ impl Voice {
         self.released_at.is_none()
     }
 
+    fn steal(&mut self) {
+        self.stolen_at = Some(self.clock())
+    }
+
+    fn is_stolen(&self) -> bool {
+        self.stolen_at.is_some()
+    }
+
+    fn fade(&self) -> Proportion {
+        self.stolen_at.map(|stolen| (1. - (self.clock() - stolen) / STEAL_FADE).max(0.)).unwrap_or(1.)
+    }
+
     fn is_finished(&self, decay: Seconds) -> bool {
         let now = self.clock.get();
-        self.released_at.map(|released| now - released > decay).unwrap_or(false)
+        let faded = self.stolen_at.map(|stolen| now - stolen > STEAL_FADE).unwrap_or(false);
+        faded || self.released_at.map(|released| now - released > decay).unwrap_or(false)
     }
 
 }
@@ -266,6 +808,10 @@ impl Clock {
         Clock{ sample_rate, clock: 0. }
     }
 
+    fn reset(&mut self) {
+        self.clock = 0.;
+    }
+
     fn tick(&mut self) -> Seconds {
         self.clock += 1.0;
         self.get()
@@ -281,14 +827,21 @@ impl Default for Specs {
     fn default() -> Self {
         Specs {
             max_voices: 8,
-            oscillator: oscillator::Specs::default(),
+            voice_mode: VoiceMode::Poly,
+            voice_stealing: VoiceStealing::Oldest,
+            velocity_curve: VelocityCurve::Linear,
+            oscillators: section::Specs::default(),
             filter: filter::Specs::default(),
-            lfo: None,
+            filter_mode: FilterMode::Global,
+            lfos: vec![],
+            mod_envelopes: vec![],
+            mod_matrix: vec![],
             adsr: Adsr::default(),
             volume: 1.,
-            modulation_x: ModTarget::default(),
-            modulation_y: ModTarget::default(),
-            modulation_lfo: ModSpecs::default(),
+            pan: 0.,
+            glide: 0.,
+            bend_range: 2,
+            smoothing: DEFAULT_SMOOTHING,
         }
     }
 }
@@ -299,8 +852,324 @@ impl Default for ModTarget {
     }
 }
 
-impl Default for ModSpecs {
-    fn default() -> Self {
-        ModSpecs { target: ModTarget::Noop, amount: 1. }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::music_theory::pitch_class::PitchClass;
+
+    const SAMPLE_RATE: Hz = 44100.;
+
+    #[test]
+    fn glides_from_previous_note() {
+        let mut voice = Voice::new(SAMPLE_RATE, Pitch::new(PitchClass::A, 5), 1., 1);
+        voice.glide_from = Some(Pitch::new(PitchClass::A, 4).freq());
+        assert!((voice.freq(0.1) - 440.).abs() < 1e-9);
+        (0..2205).for_each(|_| { voice.clock.tick(); });
+        assert!((voice.freq(0.1) - 440. * 2_f64.sqrt()).abs() < 1e-6);
+        (0..2205).for_each(|_| { voice.clock.tick(); });
+        assert!((voice.freq(0.1) - 880.).abs() < 1e-9);
+    }
+
+    fn playing(instrument: &Instrument) -> Vec<(Pitch, bool)> {
+        instrument.voices.voices.iter().map(|v| (v.pitch, v.is_holding())).collect()
+    }
+
+    #[test]
+    fn mono_returns_to_previous_note() {
+        let (a, c) = (Pitch::new(PitchClass::A, 4), Pitch::new(PitchClass::C, 5));
+        let mut instrument = Instrument::new(Specs { voice_mode: VoiceMode::Mono, ..Default::default() }, SAMPLE_RATE).unwrap();
+        instrument.hold(a, 1.);
+        instrument.hold(c, 1.);
+        assert_eq!(playing(&instrument), vec![(c, true)]);
+        instrument.release(c);
+        assert_eq!(playing(&instrument), vec![(a, true)]);
+        instrument.release(a);
+        assert_eq!(playing(&instrument), vec![(a, false)]);
+    }
+
+    #[test]
+    fn legato_keeps_envelope() {
+        let (a, c) = (Pitch::new(PitchClass::A, 4), Pitch::new(PitchClass::C, 5));
+        let clock_after_change = |mode| {
+            let mut instrument = Instrument::new(Specs { voice_mode: mode, ..Default::default() }, SAMPLE_RATE).unwrap();
+            instrument.hold(a, 1.);
+            (0..100).for_each(|_| { instrument.next_sample(); });
+            instrument.hold(c, 1.);
+            instrument.voices.voices[0].clock()
+        };
+        assert_eq!(clock_after_change(VoiceMode::Mono), 0.);
+        assert!(clock_after_change(VoiceMode::Legato) > 0.);
+    }
+
+    fn stealing(stealing: VoiceStealing, pitches: &[Pitch]) -> Instrument {
+        let mut instrument = Instrument::new(Specs { max_voices: 2, voice_stealing: stealing, ..Default::default() }, SAMPLE_RATE).unwrap();
+        for pitch in pitches {
+            instrument.hold(*pitch, 1.);
+            instrument.next_sample();
+        }
+        instrument
+    }
+
+    #[test]
+    fn steals_by_policy() {
+        let (a, c, e) = (Pitch::new(PitchClass::A, 4), Pitch::new(PitchClass::C, 5), Pitch::new(PitchClass::E, 4));
+        let stolen = |instrument: &Instrument| instrument.voices.voices.iter()
+            .filter(|v| v.is_stolen()).map(|v| v.pitch).collect::<Vec<_>>();
+        assert_eq!(stolen(&stealing(VoiceStealing::Oldest, &[a, c, e])), vec![a]);
+        assert_eq!(stolen(&stealing(VoiceStealing::Highest, &[a, c, e])), vec![c]);
+        assert_eq!(stolen(&stealing(VoiceStealing::Lowest, &[c, a, e])), vec![a]);
+        assert_eq!(stolen(&stealing(VoiceStealing::SamePitch, &[a, a])), vec![a]);
+    }
+
+    #[test]
+    fn stolen_voice_fades_out() {
+        let (a, c, e) = (Pitch::new(PitchClass::A, 4), Pitch::new(PitchClass::C, 5), Pitch::new(PitchClass::E, 4));
+        let mut instrument = stealing(VoiceStealing::Oldest, &[a, c, e]);
+        assert_eq!((instrument.view().active_voices, instrument.voices.voices.len()), (2, 3));
+        (0..(STEAL_FADE * SAMPLE_RATE) as usize + 1).for_each(|_| { instrument.next_sample(); });
+        assert_eq!(playing(&instrument), vec![(c, true), (e, true)]);
+    }
+
+    #[test]
+    fn per_voice_filter_envelope_opens_cutoff() {
+        let loudness = |filter_mode| {
+            let filter = filter::Specs { cutoff: 0., ..Default::default() };
+            let mut instrument = Instrument::new(Specs { filter, filter_mode, ..Default::default() }, SAMPLE_RATE).unwrap();
+            instrument.hold(Pitch::new(PitchClass::A, 4), 1.);
+            (0..1000).map(|_| instrument.next_sample().to_mono().abs()).sum::<f64>()
+        };
+        let envelope = Adsr::new(0., 0., 1., 0.);
+        assert!(loudness(FilterMode::Global) < 1e-6);
+        assert!(loudness(FilterMode::PerVoice { envelope, amount: 0. }) < 1e-6);
+        assert!(loudness(FilterMode::PerVoice { envelope, amount: 0.5 }) > 100.);
+    }
+
+    #[test]
+    fn cutoff_follows_key_and_velocity() {
+        let specs = Specs {
+            filter: filter::Specs { key_tracking: 1., velocity_sensitivity: 0.5, ..Default::default() },
+            ..Default::default()
+        };
+        let instrument = Instrument::new(specs, SAMPLE_RATE).unwrap();
+        assert!((instrument.cutoff_ratio(KEY_TRACKING_CENTER, 1.) - 1.).abs() < 1e-9);
+        assert!((instrument.cutoff_ratio(KEY_TRACKING_CENTER * 2., 1.) - 2.).abs() < 1e-9);
+        assert!((instrument.cutoff_ratio(KEY_TRACKING_CENTER, 0.5) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn mod_sources_add_up() {
+        let loudness = |mod_wheel, aftertouch| {
+            let mod_matrix = vec![
+                ModSlot::new(ModSource::ModWheel, ModTarget::Volume, -0.5),
+                ModSlot::new(ModSource::Aftertouch, ModTarget::Volume, -0.5),
+            ];
+            let mut instrument = Instrument::new(Specs { mod_matrix, ..Default::default() }, SAMPLE_RATE).unwrap();
+            instrument.set_mod_wheel(mod_wheel);
+            instrument.set_aftertouch(aftertouch);
+            instrument.hold(Pitch::new(PitchClass::A, 4), 1.);
+            (0..1000).map(|_| instrument.next_sample().to_mono().abs()).sum::<f64>()
+        };
+        let (none, wheel, both) = (loudness(0., 0.), loudness(1., 0.), loudness(1., 1.));
+        assert!((wheel / none - 0.5).abs() < 1e-6);
+        assert!(both < 1e-6);
+    }
+
+    #[test]
+    fn voice_sources_modulate_each_voice() {
+        let loudness = |velocity| {
+            let specs = Specs {
+                filter_mode: FilterMode::PerVoice { envelope: Adsr::default(), amount: 0. },
+                mod_matrix: vec![ModSlot::new(ModSource::Velocity, ModTarget::Filter(filter::ModTarget::Cutoff), -1.)],
+                ..Default::default()
+            };
+            let mut instrument = Instrument::new(specs, SAMPLE_RATE).unwrap();
+            instrument.hold(Pitch::new(PitchClass::A, 4), velocity);
+            (0..1000).map(|_| instrument.next_sample().to_mono().abs()).sum::<f64>()
+        };
+        assert!(loudness(1.) < 1e-6);
+        assert!(loudness(0.5) > 10.);
+    }
+
+    #[test]
+    fn voice_sources_in_global_filter_mode() {
+        let loudness = |target, amount, velocity| {
+            let specs = Specs { volume: 0.2, mod_matrix: vec![ModSlot::new(ModSource::Velocity, target, amount)], ..Default::default() };
+            let mut instrument = Instrument::new(specs, SAMPLE_RATE).unwrap();
+            instrument.hold(Pitch::new(PitchClass::A, 4), velocity);
+            (0..1000).map(|_| instrument.next_sample().to_mono().abs()).sum::<f64>()
+        };
+        let cutoff = ModTarget::Filter(filter::ModTarget::Cutoff);
+        assert!(loudness(cutoff, -1., 1.) < 1e-6);
+        assert!(loudness(cutoff, -1., 0.5) > 10.);
+        let louder = loudness(ModTarget::Volume, 0.2, 1.) / loudness(ModTarget::Volume, 0., 1.);
+        assert!((louder - 2.).abs() < 1e-6, "{}", louder);
+    }
+
+    #[test]
+    fn bipolar_velocity() {
+        let loudness = |velocity, polarity| {
+            let slot = ModSlot::new(ModSource::Velocity, ModTarget::Volume, 0.1).with_polarity(polarity);
+            let mut instrument = Instrument::new(Specs { volume: 0.2, mod_matrix: vec![slot], ..Default::default() }, SAMPLE_RATE).unwrap();
+            instrument.hold(Pitch::new(PitchClass::A, 4), velocity);
+            (0..1000).map(|_| instrument.next_sample().to_mono().abs()).sum::<f64>()
+        };
+        let ratio = |velocity| loudness(velocity, Polarity::Bipolar) / loudness(velocity, Polarity::Unipolar);
+        assert!((ratio(0.5) - 0.2 / 0.25).abs() < 1e-6, "{}", ratio(0.5));
+        assert!((ratio(1.) - 0.3 / 0.3).abs() < 1e-6, "{}", ratio(1.));
+        assert!((ratio(0.25) - 0.15 / 0.225).abs() < 1e-6, "{}", ratio(0.25));
+    }
+
+    #[test]
+    fn mod_envelope_per_voice() {
+        for filter_mode in [FilterMode::Global, FilterMode::PerVoice { envelope: Adsr::default(), amount: 0. }] {
+            let pluck = Dahdsr::new(0., 0., 0.01, 0.05, 0., 0.);
+            let specs = Specs {
+                filter: filter::Specs { cutoff: 0.01, ..Default::default() },
+                filter_mode,
+                mod_envelopes: vec![pluck],
+                mod_matrix: vec![ModSlot::new(ModSource::Envelope(0), ModTarget::Filter(filter::ModTarget::Cutoff), 0.5)],
+                ..Default::default()
+            };
+            let mut instrument = Instrument::new(specs, SAMPLE_RATE).unwrap();
+            instrument.hold(Pitch::new(PitchClass::A, 4), 1.);
+            let mut loudness = |n| (0..n).map(|_| instrument.next_sample().to_mono().abs()).sum::<f64>();
+            let plucked = loudness(441);
+            loudness(4410);
+            assert!(loudness(441) < plucked / 4.);
+        }
+    }
+
+    #[test]
+    fn key_tracking_in_global_filter_mode() {
+        let loudness = |key_tracking| {
+            let specs = Specs {
+                filter: filter::Specs { cutoff: 0.01, key_tracking, ..Default::default() },
+                ..Default::default()
+            };
+            let mut instrument = Instrument::new(specs, SAMPLE_RATE).unwrap();
+            assert_eq!(instrument.view().filter.key_tracking, key_tracking);
+            instrument.hold(Pitch::new(PitchClass::A, 6), 1.);
+            (0..4410).map(|_| instrument.next_sample().to_mono().abs()).sum::<f64>()
+        };
+        assert!(loudness(1.) > 4. * loudness(0.), "{} vs {}", loudness(1.), loudness(0.));
+    }
+
+    #[test]
+    fn vibrato_goes_both_ways() {
+        let specs = Specs {
+            lfos: vec![lfo::Specs::simple(5.)],
+            mod_matrix: vec![ModSlot::new(ModSource::Lfo(0), ModTarget::Pitch, 1.)],
+            ..Default::default()
+        };
+        let mut instrument = Instrument::new(specs, SAMPLE_RATE).unwrap();
+        instrument.hold(Pitch::new(PitchClass::A, 4), 1.);
+        let (mut lowest, mut highest) = (f64::MAX, f64::MIN);
+        for _ in 0..44100 {
+            instrument.next_sample();
+            lowest = lowest.min(instrument.pitch_offset());
+            highest = highest.max(instrument.pitch_offset());
+        }
+        let semitone = 2_f64.powf(1. / 12.);
+        assert!((lowest * semitone - 1.).abs() < 1e-3, "lowest: {}", lowest);
+        assert!((highest / semitone - 1.).abs() < 1e-3, "highest: {}", highest);
+    }
+
+    #[test]
+    fn ignores_zero_tempo() {
+        let specs = Specs { lfos: vec![lfo::Specs::simple(5.)], ..Default::default() };
+        let mut instrument = Instrument::new(specs, SAMPLE_RATE).unwrap();
+        instrument.set_tempo(0.5);
+        instrument.set_tempo(0.);
+        instrument.set_tempo(-1.);
+        assert_eq!(instrument.beat, 0.5);
+    }
+
+    #[test]
+    fn velocity_curves() {
+        assert_eq!(VelocityCurve::Linear.apply(0.5), 0.5);
+        assert_eq!(VelocityCurve::Fixed(0.8).apply(0.1), 0.8);
+        assert_eq!(VelocityCurve::Exponential.apply(1.), 1.);
+        assert!((VelocityCurve::Exponential.apply(0.5) - 0.1).abs() < 1e-9);
+        assert_eq!(VelocityCurve::Exponential.apply(0.), 0.);
+    }
+
+    #[test]
+    fn velocity_shortens_attack() {
+        let level_after_attack = |velocity| {
+            let specs = Specs {
+                adsr: Adsr::new(0.01, 0., 1., 0.),
+                mod_matrix: vec![ModSlot::new(ModSource::Velocity, ModTarget::Attack, -0.5)],
+                ..Default::default()
+            };
+            let mut instrument = Instrument::new(specs, SAMPLE_RATE).unwrap();
+            instrument.hold(Pitch::new(PitchClass::A, 4), velocity);
+            (0..110).for_each(|_| { instrument.next_sample(); });
+            instrument.voices.voices[0].level / velocity
+        };
+        assert!((level_after_attack(1.) - 1.).abs() < 1e-9);
+        assert!((level_after_attack(0.5) - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn process_same_as_next_sample() {
+        let new = || {
+            let mut instrument = Instrument::new(Specs::default(), SAMPLE_RATE).unwrap();
+            instrument.hold(Pitch::new(PitchClass::A, 4), 1.);
+            instrument
+        };
+        let mut by_sample = new();
+        let expected: Vec<Frame> = (0..100).map(|_| by_sample.next_sample()).collect();
+        let mut block = [Frame::default(); 100];
+        new().process(&mut block);
+        assert_eq!(block.to_vec(), expected);
+    }
+
+    #[test]
+    fn pans_output() {
+        let loudness = |pan| {
+            let mut instrument = Instrument::new(Specs { pan, ..Default::default() }, SAMPLE_RATE).unwrap();
+            instrument.hold(Pitch::new(PitchClass::A, 4), 1.);
+            (0..1000).map(|_| instrument.next_sample())
+                .fold((0., 0.), |(l, r), frame| (l + frame.left.abs(), r + frame.right.abs()))
+        };
+        let (left, right) = loudness(0.);
+        assert!(left > 0. && (left - right).abs() < 1e-9);
+        let (left, right) = loudness(-1.);
+        assert!(left > 0. && right == 0.);
+        let (left, right) = loudness(0.5);
+        assert!(right > left && left > 0.);
+    }
+
+    #[test]
+    fn smooths_xy_steps() {
+        let max_step = |smoothing| {
+            let specs = Specs { smoothing, mod_matrix: vec![ModSlot::new(ModSource::X, ModTarget::Volume, 1.)],
+                                oscillators: section::Specs::single(oscillator::Specs::Basic(oscillator::Basic::Sine, oscillator::Quality::Naive)),
+                                adsr: Adsr::new(0., 0., 1., 0.), ..Default::default() };
+            let mut instrument = Instrument::new(specs, SAMPLE_RATE).unwrap();
+            instrument.set_xy_params(1., 0.);
+            instrument.hold(Pitch::new(PitchClass::A, 1), 1.);
+            let mut previous = 0.;
+            (0..4410).map(|i| {
+                if i % 100 == 0 { instrument.set_xy_params((i / 100 % 2) as f64, 0.); }
+                let sample = instrument.next_sample().left;
+                let step = (sample - previous).abs();
+                previous = sample;
+                step
+            }).fold(0., f64::max)
+        };
+        let sine_slope = 2. * std::f64::consts::PI * Pitch::new(PitchClass::A, 1).freq() / SAMPLE_RATE;
+        assert!(max_step(0.) > 0.1);
+        assert!(max_step(0.01) < sine_slope + 1. / (0.01 * SAMPLE_RATE));
+    }
+
+    #[test]
+    fn pitch_bend_within_range() {
+        let mut instrument = Instrument::new(Specs { bend_range: 12, ..Default::default() }, SAMPLE_RATE).unwrap();
+        assert!((instrument.pitch_offset() - 1.).abs() < 1e-9);
+        instrument.set_pitch_bend(1.);
+        assert!((instrument.pitch_offset() - 2.).abs() < 1e-9);
+        instrument.set_pitch_bend(-2.);
+        assert!((instrument.pitch_offset() - 0.5).abs() < 1e-9);
     }
 }
@@ -1,50 +1,210 @@
-use super::oscillator::{self, Oscillator, Basic::Sine};
+use super::oscillator::{self, Oscillator, Basic::Sine, Quality::Naive};
 use crate::core::synth::Seconds;
-use crate::core::music_theory::Hz;
+use crate::core::music_theory::{Hz, rhythm::NoteDuration};
+
+/// Note durations count sixteenths
+const SIXTEENTHS_PER_BEAT: f64 = 4.;
+/// 120 bpm, until a tempo is set
+pub const DEFAULT_BEAT: Seconds = 0.5;
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct Specs {
     pub oscillator: oscillator::Specs,
-    pub freq: Hz,
+    pub rate: Rate,
     pub phase: Seconds,
+    pub trigger: Trigger,
+    /// Fade in after each note starts, 0 to start at full depth
+    pub delay: Seconds,
 }
 impl Specs {
     pub fn simple(freq: Hz) -> Specs {
-        Specs { freq, oscillator: oscillator::Specs::Basic(Sine), phase: 0. }
+        Specs { rate: Rate::Hz(freq), oscillator: oscillator::Specs::Basic(Sine, Naive), phase: 0.,
+                trigger: Trigger::Free, delay: 0. }
+    }
+
+    /// Holds a random value for each cycle
+    pub fn random(freq: Hz) -> Specs {
+        Specs { oscillator: oscillator::Specs::Noise(oscillator::NoiseColor::White), ..Specs::simple(freq) }
+    }
+
+    /// One cycle per `duration` at the current tempo
+    pub fn synced(duration: NoteDuration) -> Specs {
+        Specs { rate: Rate::Sync(duration), ..Specs::simple(1.) }
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Rate {
+    Hz(Hz),
+    /// One cycle per note duration, following the tempo
+    Sync(NoteDuration),
+}
+
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub enum Trigger {
+    /// Keeps running regardless of notes
+    #[default]
+    Free,
+    /// Restarts the cycle when a note starts
+    Retrigger,
+}
+
 pub struct LFO {
     oscillator: Box<dyn Oscillator>,
-    freq: Hz,
+    rate: Rate,
     phase: Seconds,
+    trigger: Trigger,
+    delay: Seconds,
+    sample_and_hold: bool,
+    sample_rate: Hz,
+    state: State,
+}
+
+/// Where the LFO is in its cycle, kept across patch changes
+#[derive(Clone, PartialEq, Debug)]
+pub struct State {
+    oscillator: Vec<f64>,
+    cycle: f64,
+    held: Option<f64>,
+    since_trigger: Seconds,
+    beat: Seconds,
 }
 
 impl LFO {
-    pub fn new(specs: Specs) -> LFO {
-        LFO {
-            oscillator: <dyn Oscillator>::new(&specs.oscillator),
-            freq: specs.freq,
+    pub fn new(specs: Specs, sample_rate: Hz) -> LFO {
+        let oscillator = <dyn Oscillator>::new(&specs.oscillator, sample_rate);
+        let mut lfo = LFO {
+            state: State {
+                oscillator: vec![0.; oscillator.state_size()],
+                cycle: 0.,
+                held: None,
+                since_trigger: specs.delay,
+                beat: DEFAULT_BEAT,
+            },
+            oscillator, sample_rate,
+            rate: specs.rate,
             phase: specs.phase,
+            trigger: specs.trigger,
+            delay: specs.delay,
+            sample_and_hold: matches!(specs.oscillator, oscillator::Specs::Noise(_)),
+        };
+        lfo.state.cycle = (lfo.phase * lfo.freq()).rem_euclid(1.);
+        lfo
+    }
+
+    pub fn next_sample(&mut self) -> f64 {
+        let freq = self.freq();
+        let value = if self.sample_and_hold {
+            let cycle = self.state.cycle + freq / self.sample_rate;
+            self.state.cycle = cycle % 1.;
+            let oscillator = &self.oscillator;
+            let oscillator_state = &mut self.state.oscillator;
+            match self.state.held {
+                Some(held) if cycle < 1. => held,
+                _ => *self.state.held.insert(oscillator.next_sample(oscillator_state, freq, 0.)),
+            }
+        } else {
+            self.oscillator.next_sample(&mut self.state.oscillator, freq, self.phase * freq)
+        };
+        let fade = if self.delay > 0. { (self.state.since_trigger / self.delay).min(1.) } else { 1. };
+        self.state.since_trigger += 1. / self.sample_rate;
+        value * fade
+    }
+
+    /// A note started: fades in again and restarts the cycle if retriggering
+    pub fn trigger(&mut self) {
+        self.state.since_trigger = 0.;
+        if self.trigger == Trigger::Retrigger {
+            let position = (self.phase * self.freq()).rem_euclid(1.);
+            self.oscillator.restart(&mut self.state.oscillator, 0.);
+            self.state.cycle = position;
+            self.state.held = None;
+        }
+    }
+
+    /// Duration of a quarter note, for synced rates
+    pub fn set_beat(&mut self, beat: Seconds) {
+        assert!(beat > 0., "beat was: {}", beat);
+        self.state.beat = beat;
+    }
+
+    pub fn freq(&self) -> Hz {
+        match self.rate {
+            Rate::Hz(freq) => freq,
+            Rate::Sync(duration) => SIXTEENTHS_PER_BEAT / (self.state.beat * f64::from(duration as u8)),
         }
     }
 
-    pub fn next(&self, clock: Seconds) -> f64 {
-        self.oscillator.next_sample(clock, self.freq, self.phase)
+    pub fn state(&self) -> State {
+        self.state.clone()
+    }
+
+    pub fn set_state(&mut self, state: State) {
+        if state.oscillator.len() == self.state.oscillator.len() {
+            self.state = state;
+        }
     }
 
     pub fn view(&self) -> View {
         View {
             oscillator: self.oscillator.view(),
-            freq: self.freq,
+            rate: self.rate,
+            freq: self.freq(),
             phase: self.phase,
+            trigger: self.trigger,
+            delay: self.delay,
         }
     }
 }
 
-#[derive(Clone, PartialEq, Default, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct View {
-    oscillator: oscillator::View,
-    freq: Hz,
-    phase: Seconds,
+    pub oscillator: oscillator::View,
+    pub rate: Rate,
+    /// Resulting from the rate and the tempo
+    pub freq: Hz,
+    pub phase: Seconds,
+    pub trigger: Trigger,
+    pub delay: Seconds,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: Hz = 1000.;
+
+    #[test]
+    fn synced_to_tempo() {
+        let mut lfo = LFO::new(Specs::synced(NoteDuration::Half), SAMPLE_RATE);
+        assert_eq!(lfo.freq(), 1.);
+        lfo.set_beat(0.25);
+        assert_eq!(lfo.freq(), 2.);
+        lfo.set_beat(1.);
+        assert_eq!(LFO::new(Specs::synced(NoteDuration::Sixteenth), SAMPLE_RATE).freq(), 8.);
+    }
+
+    #[test]
+    fn retrigger_restarts_cycle() {
+        let first_samples = |trigger| {
+            let mut lfo = LFO::new(Specs { trigger, ..Specs::simple(3.) }, SAMPLE_RATE);
+            (0..123).for_each(|_| { lfo.next_sample(); });
+            lfo.trigger();
+            (0..10).map(|_| lfo.next_sample()).collect::<Vec<_>>()
+        };
+        let mut fresh = LFO::new(Specs::simple(3.), SAMPLE_RATE);
+        let fresh: Vec<f64> = (0..10).map(|_| fresh.next_sample()).collect();
+        assert_eq!(first_samples(Trigger::Retrigger), fresh);
+        assert_ne!(first_samples(Trigger::Free), fresh);
+    }
+
+    #[test]
+    fn fades_in_after_delay() {
+        let mut lfo = LFO::new(Specs { delay: 0.5, ..Specs::random(10.) }, SAMPLE_RATE);
+        lfo.trigger();
+        let peak = |lfo: &mut LFO, n| (0..n).map(|_| lfo.next_sample().abs()).fold(0., f64::max);
+        assert!(peak(&mut lfo, 50) < 0.05);
+        peak(&mut lfo, 500);
+        assert!(peak(&mut lfo, 1000) > 0.5);
+    }
 }
@@ -1,13 +1,18 @@
 
 pub mod instrument;
 pub mod oscillator;
+pub mod section;
 pub mod filter;
 pub mod adsr;
 pub mod builder;
 pub mod lfo;
 pub mod modulated;
+mod frame;
+
+pub use self::frame::Frame;
 
 pub type Sample = f64;
 pub type Seconds = f64;
 pub type Proportion = f64;
-pub type Velocity = f64;
\ No newline at end of file
+pub type Velocity = f64;
+pub type Pan = f64; // -1 is left, 0 is center, 1 is right
\ No newline at end of file
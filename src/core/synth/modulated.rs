@@ -1,41 +1,98 @@
 
+use super::Seconds;
+use crate::core::music_theory::Hz;
+
 pub trait Modulated<T> {
     fn mod_param(&mut self, target: T) -> Option<&mut ModParam>;
 }
 
+/// How a modulation source from 0 to 1 moves a parameter
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub enum Polarity {
+    /// Only towards one side of the base, depending on the sign of the amount
+    #[default]
+    Unipolar,
+    /// Around the base, the source centered at 0
+    Bipolar,
+}
+impl Polarity {
+    pub fn apply(self, value: f64) -> f64 {
+        match self {
+            Polarity::Unipolar => value,
+            Polarity::Bipolar => value * 2. - 1.,
+        }
+    }
+}
+
+/// A parameter from `min` to `max`, moved around its base by modulation signals.
+/// The base and the result are normalized from 0 to 1.
 #[derive(Debug)]
 pub struct ModParam {
     pub base: f64,
     mod_signal: f64,
+    /// Modulation from the voice being rendered, on top of `mod_signal`
+    voice_signal: f64,
+    /// Proportion of the way to the target covered each `smooth`, 1 to jump right there
+    smoothing: f64,
+    /// Base plus modulation as of the last `smooth`, if it was ever called
+    smoothed: Option<f64>,
     min: f64,
     range: f64,
 }
 impl ModParam {
     pub fn with_bounds(min: f64, max: f64) -> ModParam {
         let range = max - min;
-        ModParam { base: 1., mod_signal: 0., min, range }
+        ModParam { base: 1., min, range, ..Default::default() }
     }
     pub fn with_base(base: f64, min: f64, max: f64) -> ModParam {
         let range = max - min;
         let bounded_base = base.max(0.).min(1.);
-        ModParam { base: bounded_base, mod_signal: 0., min, range }
+        ModParam { base: bounded_base, min, range, ..Default::default() }
     }
     pub fn set_base(&mut self, value: f64) {
         self.base = value.max(0.).min(1.);
     }
-    pub fn set_signal(&mut self, value: f64) {
-        self.mod_signal = value.max(0.).min(1.);
+    /// Offset from the base, positive or negative. Sums with what other sources
+    /// already added, bounded when calculating.
+    pub fn add_signal(&mut self, value: f64) {
+        self.mod_signal += value;
+    }
+    pub fn add_voice_signal(&mut self, value: f64) {
+        self.voice_signal += value;
+    }
+    pub fn clear_signal(&mut self) {
+        self.mod_signal = 0.;
+        self.voice_signal = 0.;
+    }
+    pub fn clear_voice_signal(&mut self) {
+        self.voice_signal = 0.;
+    }
+    /// One pole smoothing of base and modulation changes, reaching about 63% of a step
+    /// after `time`. Only applies once `smooth` is called every sample.
+    pub fn set_smoothing(&mut self, time: Seconds, sample_rate: Hz) {
+        self.smoothing = if time > 0. { 1. - (-1. / (time * sample_rate)).exp() } else { 1. };
+    }
+    /// Moves towards the current base plus modulation, to be called every sample
+    pub fn smooth(&mut self) {
+        let target = self.base + self.mod_signal;
+        let current = self.smoothed.unwrap_or(target);
+        self.smoothed = Some(current + (target - current) * self.smoothing);
     }
     pub fn normalized(&self) -> f64 {
-        (1. - self.mod_signal) * self.base
+        let value = self.smoothed.unwrap_or(self.base + self.mod_signal);
+        (value + self.voice_signal).clamp(0., 1.)
     }
     pub fn calculate(&self) -> f64 {
         self.normalized() * self.range + self.min
     }
+    /// Like `calculate`, with `shift` added to the normalized value and kept in bounds
+    pub fn calculate_shifted(&self, shift: f64) -> f64 {
+        (self.normalized() + shift).clamp(0., 1.) * self.range + self.min
+    }
 }
 impl Default for ModParam {
     fn default() -> Self {
-        ModParam { base: 1., mod_signal: 0., min: 0., range: 1. }
+        ModParam { base: 1., mod_signal: 0., voice_signal: 0., smoothing: 1., smoothed: None, min: 0., range: 1. }
     }
 }
 
@@ -47,48 +104,109 @@ mod tests {
     #[test]
     fn up_and_down() {
         let mut sut = ModParam::with_bounds(0.,  100.);
-        sut.set_signal(0.);
-        assert_approx(sut.calculate(), 100.);
-        sut.set_signal(0.01);
-        assert_approx(sut.calculate(), 99.);
-        sut.set_signal(0.5);
-        assert_approx(sut.calculate(), 50.);
-        sut.set_signal(0.99);
-        assert_approx(sut.calculate(), 1.);
-        sut.set_signal(1.);
-        assert_approx(sut.calculate(), 0.);
+        for (source, expected) in [(0., 100.), (0.01, 99.), (0.5, 50.), (0.99, 1.), (1., 0.)] {
+            sut.clear_signal();
+            sut.add_signal(Polarity::Unipolar.apply(source) * -1.);
+            assert_approx(sut.calculate(), expected);
+        }
     }
 
     #[test]
     fn out_of_bounds() {
         let mut sut = ModParam::with_bounds(0.,  1.);
-        sut.set_signal(-1.);
+        sut.add_signal(1.);
         assert_approx(sut.calculate(), 1.);
-        sut.set_signal(2.);
+        sut.clear_signal();
+        sut.add_signal(-2.);
         assert_approx(sut.calculate(), 0.);
     }
 
     #[test]
     fn negative_min() {
         let mut sut = ModParam::with_bounds(-10.,  10.);
-        sut.set_signal(0.);
         assert_approx(sut.calculate(), 10.);
-        sut.set_signal(0.5);
+        sut.add_signal(-0.5);
         assert_approx(sut.calculate(), 0.);
-        sut.set_signal(1.);
+        sut.add_signal(-0.5);
         assert_approx(sut.calculate(), -10.);
     }
 
     #[test]
-    fn base_at_half() {
+    fn sources_add_up() {
         let mut sut = ModParam::with_bounds(0.,  10.);
-        sut.set_base(0.5);
-        sut.set_signal(0.);
+        sut.add_signal(-0.25);
+        sut.add_signal(-0.25);
         assert_approx(sut.calculate(), 5.);
-        sut.set_signal(0.5);
+        sut.add_voice_signal(-0.25);
         assert_approx(sut.calculate(), 2.5);
-        sut.set_signal(1.);
+        sut.add_voice_signal(-1.);
         assert_approx(sut.calculate(), 0.);
+        sut.clear_voice_signal();
+        assert_approx(sut.calculate(), 5.);
+        sut.clear_signal();
+        assert_approx(sut.calculate(), 10.);
+    }
+
+    #[test]
+    fn unipolar_both_ways() {
+        let mut sut = ModParam::with_base(0.5, 0.,  10.);
+        sut.add_signal(Polarity::Unipolar.apply(0.4) * 0.5);
+        assert_approx(sut.calculate(), 7.);
+        sut.clear_signal();
+        sut.add_signal(Polarity::Unipolar.apply(0.4) * -0.5);
+        assert_approx(sut.calculate(), 3.);
+        sut.clear_signal();
+        sut.add_signal(Polarity::Unipolar.apply(0.) * -0.5);
+        assert_approx(sut.calculate(), 5.);
+    }
+
+    #[test]
+    fn bipolar_around_base() {
+        let mut sut = ModParam::with_base(0.5, -100.,  100.);
+        for (source, expected) in [(0., -50.), (0.25, -25.), (0.5, 0.), (1., 50.)] {
+            sut.clear_signal();
+            sut.add_signal(Polarity::Bipolar.apply(source) * 0.25);
+            assert_approx(sut.calculate(), expected);
+        }
+        sut.clear_signal();
+        sut.add_signal(Polarity::Bipolar.apply(1.) * -0.25);
+        assert_approx(sut.calculate(), -50.);
+    }
+
+    #[test]
+    fn clamped_to_bounds() {
+        let mut sut = ModParam::with_base(0.8, 20.,  120.);
+        sut.add_signal(Polarity::Bipolar.apply(1.));
+        assert_approx(sut.calculate(), 120.);
+        sut.clear_signal();
+        sut.add_signal(Polarity::Bipolar.apply(0.));
+        sut.add_voice_signal(-0.5);
+        assert_approx(sut.calculate(), 20.);
+        assert_approx(sut.calculate_shifted(0.3), 50.);
+    }
+
+    #[test]
+    fn smooths_steps() {
+        let mut sut = ModParam::with_bounds(0.,  1.);
+        sut.set_smoothing(0.01, 1000.);
+        sut.smooth();
+        sut.set_base(0.);
+        sut.smooth();
+        assert!(sut.calculate() > 0.9);
+        (0..9).for_each(|_| sut.smooth());
+        assert!((sut.calculate() - (-1_f64).exp()).abs() < 1e-9);
+        (0..100).for_each(|_| sut.smooth());
+        assert!(sut.calculate() < 1e-4);
+    }
+
+    #[test]
+    fn no_smoothing() {
+        let mut sut = ModParam::with_bounds(0.,  1.);
+        sut.set_smoothing(0., 1000.);
+        sut.smooth();
+        sut.set_base(0.3);
+        sut.smooth();
+        assert_approx(sut.calculate(), 0.3);
     }
 
     fn assert_approx(left: f64, right: f64) {
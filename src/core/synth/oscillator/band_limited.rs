@@ -0,0 +1,151 @@
+//! Saw, Square and Pulse with their discontinuities smoothed by PolyBLEP,
+//! which removes most of the aliasing the naive waveforms produce at high frequencies.
+//! They keep the same range and polarity as their naive counterparts.
+//!
+//! https://www.kvraudio.com/forum/viewtopic.php?t=375517
+
+use super::*;
+
+pub struct Saw {
+    sample_rate: Hz,
+}
+impl Saw {
+    pub fn new(sample_rate: Hz) -> Saw {
+        Saw { sample_rate }
+    }
+}
+impl Oscillator for Saw {
+    fn next_sample(&self, state: &mut [f64], freq: Hz, phase: Proportion) -> Sample {
+        let t = next_position(&mut state[0], freq, self.sample_rate, phase);
+        let dt = freq / self.sample_rate;
+        t - poly_blep(t, dt) / 2.
+    }
+
+    fn view(&self) -> View {
+        View::Saw
+    }
+}
+impl Modulated<ModTarget> for Saw {
+    fn mod_param(&mut self, _target: ModTarget) -> Option<&mut ModParam> { None }
+}
+
+pub struct Square {
+    sample_rate: Hz,
+}
+impl Square {
+    pub fn new(sample_rate: Hz) -> Square {
+        Square { sample_rate }
+    }
+}
+impl Oscillator for Square {
+    fn next_sample(&self, state: &mut [f64], freq: Hz, phase: Proportion) -> Sample {
+        let t = next_position(&mut state[0], freq, self.sample_rate, phase);
+        let dt = freq / self.sample_rate;
+        let naive = if t < 0.5 {-1.} else {1.};
+        naive - poly_blep(t, dt) + poly_blep((t + 0.5) % 1., dt)
+    }
+
+    fn view(&self) -> View {
+        View::Square
+    }
+}
+impl Modulated<ModTarget> for Square {
+    fn mod_param(&mut self, _target: ModTarget) -> Option<&mut ModParam> { None }
+}
+
+pub struct Pulse {
+    duty_cycle: ModParam,
+    sample_rate: Hz,
+}
+impl Pulse {
+    pub fn new(duty_cycle: Proportion, sample_rate: Hz) -> Pulse {
+        Pulse { duty_cycle: ModParam::with_base(duty_cycle, 0., 1.), sample_rate }
+    }
+}
+impl Oscillator for Pulse {
+    fn next_sample(&self, state: &mut [f64], freq: Hz, phase: Proportion) -> Sample {
+        let duty_cycle = self.duty_cycle.calculate();
+        let t = next_position(&mut state[0], freq, self.sample_rate, phase);
+        let dt = freq / self.sample_rate;
+        let naive = if t < duty_cycle {1.} else {-1.};
+        naive + poly_blep(t, dt) - poly_blep((t - duty_cycle).rem_euclid(1.), dt)
+    }
+
+    fn view(&self) -> View {
+        View::Pulse(self.duty_cycle.normalized())
+    }
+}
+impl Modulated<ModTarget> for Pulse {
+    fn mod_param(&mut self, target: ModTarget) -> Option<&mut ModParam> {
+        match target {
+            ModTarget::PulseDuty => Some(&mut self.duty_cycle),
+            _ => None
+        }
+    }
+}
+
+/// Residual of a band-limited step of height 2 at t = 0, spread over one sample on each side.
+/// `t` is the position in the cycle and `dt` the cycle proportion advanced per sample.
+fn poly_blep(t: Proportion, dt: Proportion) -> f64 {
+    if t < dt {
+        let t = t / dt;
+        t + t - t * t - 1.
+    } else if t > 1. - dt {
+        let t = (t - 1.) / dt;
+        t * t + t + t + 1.
+    } else {
+        0.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::synth::oscillator::{basic, pulse};
+    use crate::util::fft;
+
+    const SAMPLE_RATE: Hz = 44100.;
+    const N_SAMPLES: usize = 4096;
+    const FUNDAMENTAL_BIN: usize = 211;
+
+    /// Energy outside the harmonics of the fundamental relative to energy on them.
+    /// The fundamental sits exactly on a prime bin so harmonics don't leak and aliases fold between them.
+    fn aliasing(oscillator: &dyn Oscillator) -> f64 {
+        let freq = FUNDAMENTAL_BIN as f64 * SAMPLE_RATE / N_SAMPLES as f64;
+        let mut state = [0.];
+        let signal: Vec<Sample> = (0..N_SAMPLES)
+            .map(|_| oscillator.next_sample(&mut state, freq, 0.))
+            .collect();
+        let (harmonic, alias) = fft::power_spectrum(&signal).iter().enumerate().skip(1)
+            .fold((0., 0.), |(harmonic, alias), (bin, power)|
+                if bin % FUNDAMENTAL_BIN == 0 { (harmonic + power, alias) } else { (harmonic, alias + power) });
+        alias / harmonic
+    }
+
+    fn assert_less_aliasing(band_limited: &dyn Oscillator, naive: &dyn Oscillator) {
+        let (band_limited, naive) = (aliasing(band_limited), aliasing(naive));
+        assert!(band_limited * 10. < naive, "band limited: {}, naive: {}", band_limited, naive);
+    }
+
+    #[test]
+    fn saw() {
+        assert_less_aliasing(&Saw::new(SAMPLE_RATE), &basic::Saw::new(SAMPLE_RATE));
+    }
+
+    #[test]
+    fn square() {
+        assert_less_aliasing(&Square::new(SAMPLE_RATE), &basic::Square::new(SAMPLE_RATE));
+    }
+
+    #[test]
+    fn pulse() {
+        assert_less_aliasing(&Pulse::new(0.25, SAMPLE_RATE), &pulse::Pulse::new(0.25, SAMPLE_RATE));
+    }
+
+    #[test]
+    fn same_shape_as_naive_away_from_the_edges() {
+        let (band_limited, naive) = (Saw::new(SAMPLE_RATE), basic::Saw::new(SAMPLE_RATE));
+        let sample = |oscillator: &dyn Oscillator| oscillator.next_sample(&mut [0.3], 100., 0.);
+        assert!((sample(&band_limited) - sample(&naive)).abs() < 1e-9);
+    }
+}
@@ -1,11 +1,18 @@
-
 use std::f64::consts::PI;
 use super::*;
 
-pub struct Sine;
+pub struct Sine {
+    sample_rate: Hz,
+}
+impl Sine {
+    pub fn new(sample_rate: Hz) -> Sine {
+        Sine { sample_rate }
+    }
+}
 impl Oscillator for Sine {
-    fn next_sample(&self, clock: Seconds, freq: Hz, phase: Seconds) -> Sample {
-        ((clock + phase) * freq * 2. * PI).sin()
+    fn next_sample(&self, state: &mut [f64], freq: Hz, phase: Proportion) -> Sample {
+        let position = next_position(&mut state[0], freq, self.sample_rate, phase);
+        (position * 2. * PI).sin()
     }
 
     fn view(&self) -> View {
@@ -16,10 +23,17 @@ impl Modulated<ModTarget> for Sine {
     fn mod_param(&mut self, _target: ModTarget) -> Option<&mut ModParam> { None }
 }
 
-pub struct Square;
+pub struct Square {
+    sample_rate: Hz,
+}
+impl Square {
+    pub fn new(sample_rate: Hz) -> Square {
+        Square { sample_rate }
+    }
+}
 impl Oscillator for Square {
-    fn next_sample(&self, clock: Seconds, freq: Hz, phase: Seconds) -> Sample {
-        (((clock + phase) * freq ) % 1.).round() * 2. - 1.
+    fn next_sample(&self, state: &mut [f64], freq: Hz, phase: Proportion) -> Sample {
+        next_position(&mut state[0], freq, self.sample_rate, phase).round() * 2. - 1.
     }
 
     fn view(&self) -> View {
@@ -30,10 +44,17 @@ impl Modulated<ModTarget> for Square {
     fn mod_param(&mut self, _target: ModTarget) -> Option<&mut ModParam> { None }
 }
 
-pub struct Saw;
+pub struct Saw {
+    sample_rate: Hz,
+}
+impl Saw {
+    pub fn new(sample_rate: Hz) -> Saw {
+        Saw { sample_rate }
+    }
+}
 impl Oscillator for Saw {
-    fn next_sample(&self, clock: Seconds, freq: Hz, phase: Seconds) -> Sample {
-        ((clock + phase) * freq) % 1.
+    fn next_sample(&self, state: &mut [f64], freq: Hz, phase: Proportion) -> Sample {
+        next_position(&mut state[0], freq, self.sample_rate, phase)
     }
 
     fn view(&self) -> View {
@@ -43,3 +64,41 @@ impl Oscillator for Saw {
 impl Modulated<ModTarget> for Saw {
     fn mod_param(&mut self, _target: ModTarget) -> Option<&mut ModParam> { None }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: Hz = 44100.;
+
+    /// Largest jump between consecutive samples
+    fn max_step(oscillator: &dyn Oscillator, freqs: impl Iterator<Item=Hz>) -> f64 {
+        let mut state = vec![0.; oscillator.state_size()];
+        let samples: Vec<Sample> = freqs.map(|freq| oscillator.next_sample(&mut state, freq, 0.)).collect();
+        samples.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0., f64::max)
+    }
+
+    #[test]
+    fn sweep_is_continuous() {
+        let sweep = (0..44100).map(|i| 100. + 1900. * i as f64 / 44100.);
+        let max_slope = 2. * PI * 2000. / SAMPLE_RATE;
+        assert!(max_step(&Sine::new(SAMPLE_RATE), sweep) <= max_slope);
+    }
+
+    #[test]
+    fn frequency_jump_is_continuous() {
+        let jump = (0..10000).map(|i| if i < 5000 { 440. } else { 1234.5 });
+        let max_slope = 2. * PI * 1234.5 / SAMPLE_RATE;
+        assert!(max_step(&Sine::new(SAMPLE_RATE), jump) <= max_slope);
+    }
+
+    #[test]
+    fn saw_only_jumps_at_cycle_end() {
+        let sweep = (0..44100).map(|i| 50. + 500. * i as f64 / 44100.);
+        let mut state = vec![0.];
+        let saw = Saw::new(SAMPLE_RATE);
+        let samples: Vec<Sample> = sweep.map(|freq| saw.next_sample(&mut state, freq, 0.)).collect();
+        let rising_steps = samples.windows(2).map(|w| w[1] - w[0]).filter(|step| *step > 0.);
+        assert!(rising_steps.fold(0., f64::max) <= 550. / SAMPLE_RATE + 1e-12);
+    }
+}
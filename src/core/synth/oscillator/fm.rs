@@ -0,0 +1,178 @@
+use super::*;
+use std::f64::consts::PI;
+
+/// Peak phase deviation of a modulator at full level and index, in radians
+const MAX_INDEX: f64 = 4. * PI;
+/// Self modulation of the top operator at full feedback, in radians
+const MAX_FEEDBACK: f64 = 1.5;
+/// Slots holding the last output of the feedback operator, after the 4 operator phases
+const FEEDBACK_STATE: usize = 4;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Operator {
+    /// Frequency relative to the note
+    pub ratio: f64,
+    pub level: Proportion,
+}
+
+/// How the 4 operators connect, in DX style: operator 1 is always a carrier
+/// and operators only modulate lower numbered ones.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Algorithm {
+    /// 4 → 3 → 2 → 1
+    Stack,
+    /// 2 → 1 and 4 → 3, both heard
+    TwoStacks,
+    /// 2, 3 and 4 all modulate 1
+    Branch,
+    /// No modulation, all heard
+    Additive,
+}
+
+impl Algorithm {
+    fn modulators(self, operator: usize) -> &'static [usize] {
+        match (self, operator) {
+            (Algorithm::Stack, 0) => &[1],
+            (Algorithm::Stack, 1) => &[2],
+            (Algorithm::Stack, 2) => &[3],
+            (Algorithm::TwoStacks, 0) => &[1],
+            (Algorithm::TwoStacks, 2) => &[3],
+            (Algorithm::Branch, 0) => &[1, 2, 3],
+            _ => &[],
+        }
+    }
+
+    fn carriers(self) -> &'static [usize] {
+        match self {
+            Algorithm::Stack | Algorithm::Branch => &[0],
+            Algorithm::TwoStacks => &[0, 2],
+            Algorithm::Additive => &[0, 1, 2, 3],
+        }
+    }
+}
+
+///
+/// Phase modulation: each operator is a sine whose phase is pushed by the output of its modulators.
+/// Feedback makes the top operator modulate itself.
+///
+pub struct Fm {
+    operators: [Operator; 4],
+    algorithm: Algorithm,
+    index: ModParam,
+    feedback: Proportion,
+    sample_rate: Hz,
+}
+impl Fm {
+    pub fn new(operators: [Operator; 4], algorithm: Algorithm, index: Proportion, feedback: Proportion,
+               sample_rate: Hz) -> Fm {
+        Fm {
+            operators, algorithm,
+            index: ModParam::with_base(index, 0., MAX_INDEX),
+            feedback: feedback.clamp(0., 1.),
+            sample_rate,
+        }
+    }
+
+    /// `modulation` is a phase deviation in radians
+    fn operator_sample(&self, operator: usize, state: &mut [f64], freq: Hz, phase: Proportion, modulation: f64) -> Sample {
+        let op_freq = freq * self.operators[operator].ratio;
+        let offset = phase + modulation / (2. * PI);
+        let position = next_position(&mut state[operator], op_freq, self.sample_rate, offset);
+        (position * 2. * PI).sin()
+    }
+
+    /// Self modulation by the average of the last two outputs, which keeps high feedback from chattering
+    fn feedback_sample(&self, operator: usize, state: &mut [f64], freq: Hz, phase: Proportion) -> Sample {
+        let previous = (state[FEEDBACK_STATE] + state[FEEDBACK_STATE + 1]) / 2.;
+        let output = self.operator_sample(operator, state, freq, phase, self.feedback * MAX_FEEDBACK * previous);
+        state[FEEDBACK_STATE + 1] = state[FEEDBACK_STATE];
+        state[FEEDBACK_STATE] = output;
+        output
+    }
+}
+impl Oscillator for Fm {
+    fn state_size(&self) -> usize { FEEDBACK_STATE + 2 }
+
+    fn next_sample(&self, state: &mut [f64], freq: Hz, phase: Proportion) -> Sample {
+        let index = self.index.calculate();
+        let mut outputs = [0.; 4];
+        for operator in (0..4).rev() {
+            outputs[operator] = if operator == 3 {
+                self.feedback_sample(operator, state, freq, phase)
+            } else {
+                let modulation: f64 = self.algorithm.modulators(operator).iter()
+                    .map(|m| outputs[*m] * self.operators[*m].level * index)
+                    .sum();
+                self.operator_sample(operator, state, freq, phase, modulation)
+            };
+        }
+        let carriers = self.algorithm.carriers();
+        carriers.iter()
+            .map(|c| outputs[*c] * self.operators[*c].level)
+            .sum::<Sample>() / carriers.len() as f64
+    }
+
+    fn restart(&self, state: &mut [f64], position: Proportion) {
+        for (operator, accumulator) in self.operators.iter().zip(state.iter_mut()) {
+            *accumulator = (position * operator.ratio).rem_euclid(1.);
+        }
+        state[FEEDBACK_STATE..].fill(0.);
+    }
+
+    fn view(&self) -> View {
+        View::Fm {
+            algorithm: self.algorithm,
+            index: self.index.normalized(),
+        }
+    }
+}
+impl Modulated<ModTarget> for Fm {
+    fn mod_param(&mut self, target: ModTarget) -> Option<&mut ModParam> {
+        match target {
+            ModTarget::FmIndex => Some(&mut self.index),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: Hz = 44100.;
+
+    fn operators() -> [Operator; 4] {
+        [Operator { ratio: 1., level: 1. }, Operator { ratio: 2., level: 1. },
+         Operator { ratio: 3.5, level: 1. }, Operator { ratio: 7., level: 1. }]
+    }
+
+    fn max_difference_from_sine(fm: &Fm) -> f64 {
+        let sine = basic::Sine::new(SAMPLE_RATE);
+        let mut fm_state = vec![0.; fm.state_size()];
+        let mut sine_state = [0.];
+        (0..1000)
+            .map(|_| (fm.next_sample(&mut fm_state, 220., 0.) - sine.next_sample(&mut sine_state, 220., 0.)).abs())
+            .fold(0., f64::max)
+    }
+
+    #[test]
+    fn no_index_no_feedback_is_a_sine() {
+        let fm = Fm::new(operators(), Algorithm::Stack, 0., 0., SAMPLE_RATE);
+        assert!(max_difference_from_sine(&fm) < 1e-9);
+    }
+
+    #[test]
+    fn index_is_modulated() {
+        let mut fm = Fm::new(operators(), Algorithm::Stack, 0., 0., SAMPLE_RATE);
+        fm.mod_param(ModTarget::FmIndex).unwrap().set_base(0.5);
+        assert!(max_difference_from_sine(&fm) > 0.5);
+    }
+
+    #[test]
+    fn bounded_with_full_feedback() {
+        let fm = Fm::new(operators(), Algorithm::Additive, 1., 1., SAMPLE_RATE);
+        let mut state = vec![0.; fm.state_size()];
+        assert!((0..1000).map(|_| fm.next_sample(&mut state, 220., 0.))
+            .all(|s| (-1. ..=1.).contains(&s)));
+    }
+}
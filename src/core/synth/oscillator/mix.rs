@@ -1,71 +1,185 @@
 
-use crate::core::synth::{Sample, Seconds, modulated::*};
+use crate::core::synth::{Sample, Frame, Pan, modulated::*};
 use crate::core::music_theory::Hz;
 use super::*;
 use rand::{self, Rng, StdRng, SeedableRng};
 
+/// Range of the thickness modulation, unless the detune amount is already wider
+const MAX_DETUNE: Hz = 20.;
+
 pub struct Mix {
     voices: Vec<Voice>,
+    thickness: ModParam,
+    level: ModParam,
+    spread: Proportion,
 }
 
 impl Mix {
-    pub fn detuned(n_voices: usize, detune_amount: Hz, specs: Basic, random_seed: u64) -> Mix {
-        Mix { voices: create_voices(n_voices, detune_amount, specs, random_seed) }
+    pub fn detuned(n_voices: usize, detune_amount: Hz, specs: Basic, quality: Quality, random_seed: u64, sample_rate: Hz) -> Mix {
+        let max_detune = detune_amount.max(MAX_DETUNE);
+        Mix {
+            voices: create_voices(n_voices, specs, quality, random_seed, sample_rate),
+            thickness: ModParam::with_base(detune_amount / max_detune, 0., max_detune),
+            level: ModParam::with_base(1., 0., 1.),
+            spread: 0.,
+        }
+    }
+
+    pub fn with_spread(self, spread: Proportion) -> Mix {
+        Mix { spread: spread.clamp(0., 1.), ..self }
     }
 }
 
-fn create_voices(n_voices: usize, detune_amount: Hz, specs: Basic, random_seed: u64) -> Vec<Voice> {
+fn create_voices(n_voices: usize, specs: Basic, quality: Quality, random_seed: u64, sample_rate: Hz) -> Vec<Voice> {
     let mut rng = StdRng::seed_from_u64(random_seed);
-    fn random_around_zero(rng: &mut StdRng, amount: Hz) -> Hz {
-        rng.gen_range(-amount, amount)
+    fn random_around_zero(rng: &mut StdRng) -> f64 {
+        rng.gen_range(-1., 1.)
     }
 
     vec![0; n_voices].iter()
-        .map(|_| Voice::new(random_around_zero(&mut rng, detune_amount), specs))
+        .map(|_| Voice::new(random_around_zero(&mut rng), specs, quality, sample_rate))
         .collect()
 }
 
+impl Mix {
+    fn voice_state_size(&self) -> usize {
+        self.voices.first().map(|v| v.oscillator.state_size()).unwrap_or(0)
+    }
+}
+
 impl Oscillator for Mix {
-    fn next_sample(&self, clock: Seconds, freq: Hz, phase: Seconds) -> Sample {
-        self.voices.iter()
-            .map(|v| v.next_sample(clock, freq, phase))
-            .sum()
+    fn state_size(&self) -> usize {
+        self.voices.len() * self.voice_state_size()
+    }
+
+    fn next_sample(&self, state: &mut [f64], freq: Hz, phase: Proportion) -> Sample {
+        let detune_amount = self.thickness.calculate();
+        let sum: Sample = self.voices.iter()
+            .zip(state.chunks_mut(self.voice_state_size().max(1)))
+            .map(|(v, voice_state)| v.next_sample(voice_state, freq, detune_amount, phase))
+            .sum();
+        sum * self.level.calculate()
+    }
+
+    fn next_frame(&self, state: &mut [f64], freq: Hz, phase: Proportion) -> Frame {
+        let detune_amount = self.thickness.calculate();
+        let sum: Frame = self.voices.iter()
+            .zip(state.chunks_mut(self.voice_state_size().max(1)))
+            .map(|(v, voice_state)| Frame::mono(v.next_sample(voice_state, freq, detune_amount, phase))
+                .balance(v.pan(self.spread)))
+            .sum();
+        sum * self.level.calculate()
+    }
+
+    fn restart(&self, state: &mut [f64], position: Proportion) {
+        for (v, voice_state) in self.voices.iter().zip(state.chunks_mut(self.voice_state_size().max(1))) {
+            v.oscillator.restart(voice_state, position);
+        }
     }
 
     fn view(&self) -> View {
+        let detune_amount = self.thickness.calculate();
         View::Mix {
-            voices: self.voices.iter().map(|v| v.view()).collect()
+            voices: self.voices.iter().map(|v| v.view(detune_amount, self.spread)).collect(),
+            thickness: self.thickness.normalized(),
+            level: self.level.normalized(),
+            spread: self.spread,
         }
     }
 }
 
 impl Modulated<ModTarget> for Mix {
-    fn mod_param(&mut self, _target: ModTarget) -> Option<&mut ModParam> { None }
+    fn mod_param(&mut self, target: ModTarget) -> Option<&mut ModParam> {
+        match target {
+            ModTarget::MixThickness => Some(&mut self.thickness),
+            ModTarget::MixLevel => Some(&mut self.level),
+            _ => None
+        }
+    }
 }
 
 
 struct Voice {
-    tuning: Hz,
+    /// Proportion of the detune amount, between -1 and 1
+    detune: f64,
     oscillator: Box<dyn Oscillator>,
 }
 
 impl Voice {
-    pub fn new(tuning: f64, specs: Basic) -> Self {
+    pub fn new(detune: f64, specs: Basic, quality: Quality, sample_rate: Hz) -> Self {
         Self {
-            tuning,
-            oscillator: <dyn Oscillator>::new(&Specs::Basic(specs)),
+            detune,
+            oscillator: <dyn Oscillator>::new(&Specs::Basic(specs, quality), sample_rate),
         }
     }
 
-    fn next_sample(&self, clock: Seconds, freq: Hz, phase: Seconds) -> Sample {
-        let final_freq = freq + self.tuning;
-        self.oscillator.next_sample(clock, final_freq, phase)
+    fn next_sample(&self, state: &mut [f64], freq: Hz, detune_amount: Hz, phase: Proportion) -> Sample {
+        let final_freq = freq + self.detune * detune_amount;
+        self.oscillator.next_sample(state, final_freq, phase)
+    }
+
+    /// Voices tuned up go right, down go left
+    fn pan(&self, spread: Proportion) -> Pan {
+        self.detune * spread
     }
 
-    fn view(&self) -> MixVoiceView {
+    fn view(&self, detune_amount: Hz, spread: Proportion) -> MixVoiceView {
         MixVoiceView {
-            tuning: self.tuning,
+            tuning: self.detune * detune_amount,
+            pan: self.pan(spread),
             oscillator: Box::new(self.oscillator.view())
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: Hz = 44100.;
+
+    #[test]
+    fn thickness_scales_detune() {
+        let mut mix = Mix::detuned(4, 10., Basic::Saw, Quality::Naive, 0, SAMPLE_RATE);
+        let tunings = |mix: &Mix| match mix.view() {
+            View::Mix { voices, .. } => voices.iter().map(|v| v.tuning).collect::<Vec<Hz>>(),
+            _ => panic!(),
+        };
+        let full = tunings(&mix);
+        assert!(full.iter().all(|t| t.abs() <= 10.) && full.iter().any(|t| t.abs() > 1.));
+
+        // The base is at half, 10 of 20 Hz
+        mix.mod_param(ModTarget::MixThickness).unwrap().add_signal(-0.25);
+        tunings(&mix).iter().zip(full.iter()).for_each(|(half, full)| assert!((half - full / 2.).abs() < 1e-9));
+
+        mix.mod_param(ModTarget::MixThickness).unwrap().set_base(0.);
+        let unison = mix.next_sample(&mut [0.3; 4], 440., 0.);
+        assert!((unison - 4. * basic::Saw::new(SAMPLE_RATE).next_sample(&mut [0.3], 440., 0.)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn detune_beyond_modulation_range() {
+        let mix = Mix::detuned(1, 40., Basic::Sine, Quality::Naive, 0, SAMPLE_RATE);
+        let detune = mix.voices[0].detune;
+        assert!(detune.abs() > 0.1);
+        let single = basic::Sine::new(SAMPLE_RATE);
+        let (mut mix_state, mut single_state) = ([0.], [0.]);
+        for _ in 0..1000 {
+            let expected = single.next_sample(&mut single_state, 440. + detune * 40., 0.);
+            assert!((mix.next_sample(&mut mix_state, 440., 0.) - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn spread_pans_voices() {
+        let frame = |spread| {
+            let mix = Mix::detuned(4, 10., Basic::Sine, Quality::Naive, 0, SAMPLE_RATE).with_spread(spread);
+            let mut state = [0.; 4];
+            (0..1000).map(|_| mix.next_frame(&mut state, 440., 0.)).map(|f| f.map(f64::abs)).sum::<Frame>()
+        };
+        let centered = frame(0.);
+        assert_eq!(centered.left, centered.right);
+        let spread = frame(1.);
+        assert!((spread.left - spread.right).abs() > 0.1 * centered.left);
+    }
+}
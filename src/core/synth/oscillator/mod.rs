@@ -1,59 +1,132 @@
 mod mix;
 mod basic;
 mod pulse;
+mod noise;
+mod band_limited;
+mod wavetable;
+mod fm;
 
-use super::{Sample, Seconds, Proportion, modulated::*};
+use super::{Sample, Frame, Proportion, Pan, modulated::*};
 use crate::core::music_theory::Hz;
 use crate::core::synth::oscillator::basic::{Sine, Square, Saw};
 use crate::core::synth::oscillator::pulse::Pulse;
+use crate::core::synth::oscillator::noise::Noise;
+use crate::core::synth::oscillator::wavetable::Wavetable;
+use crate::core::synth::oscillator::fm::Fm;
+
+pub use self::wavetable::{Table, BuiltInTable};
+pub use self::fm::{Operator, Algorithm};
+
+const NOISE_SEED: u64 = 0;
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Basic {
     Sine, Saw, Square
 }
 
+/// Naive waveforms alias audibly at high frequencies. Band limited ones don't, at a higher cpu cost.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Quality {
+    Naive, BandLimited
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum NoiseColor {
+    White, Pink, Brown
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub enum Specs {
-    Basic(Basic),
-    Pulse(Proportion),
+    Basic(Basic, Quality),
+    Pulse(Proportion, Quality),
     Mix {
         n_voices: usize,
         detune_amount: Hz,
         specs: Basic,
+        quality: Quality,
         random_seed: u64,
-    }
+        /// How far apart the voices are panned, following their detune
+        spread: Proportion,
+    },
+    Noise(NoiseColor),
+    Wavetable {
+        table: Table,
+        position: Proportion,
+    },
+    Fm {
+        operators: Box<[Operator; 4]>,
+        algorithm: Algorithm,
+        index: Proportion,
+        feedback: Proportion,
+    },
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
-pub enum ModTarget { PulseDuty, MixThickness }
+pub enum ModTarget { PulseDuty, MixThickness, MixLevel, WavetablePosition, FmIndex }
 
+///
+/// Oscillators hold the parameters shared by all voices, while each voice owns a `state`
+/// of `state_size` values, e.g. phase accumulators, that the oscillator advances every sample.
+/// Since the phase accumulates the instantaneous frequency, pitch changes don't cause jumps.
+///
 pub trait Oscillator: Modulated<ModTarget> {
-    fn next_sample(&self, clock: Seconds, freq: Hz, phase: Seconds) -> Sample;
+    fn state_size(&self) -> usize { 1 }
+    /// `phase` is an offset in cycles, on top of the accumulated phase
+    fn next_sample(&self, state: &mut [f64], freq: Hz, phase: Proportion) -> Sample;
+    /// Stereo oscillators override it, the rest play the same on both sides
+    fn next_frame(&self, state: &mut [f64], freq: Hz, phase: Proportion) -> Frame {
+        Frame::mono(self.next_sample(state, freq, phase))
+    }
     fn view(&self) -> View;
+    /// Jumps to `position` in the cycle, used by hard sync
+    fn restart(&self, state: &mut [f64], position: Proportion) {
+        state.fill(0.);
+        if let Some(accumulator) = state.first_mut() {
+            *accumulator = position;
+        }
+    }
+}
+
+/// Position in the cycle, then advances the phase accumulator by one sample at `freq`
+pub(super) fn next_position(accumulator: &mut f64, freq: Hz, sample_rate: Hz, phase: Proportion) -> Proportion {
+    let position = (*accumulator + phase).rem_euclid(1.);
+    *accumulator = (*accumulator + freq / sample_rate).rem_euclid(1.);
+    position
 }
 
 impl dyn Oscillator {
-    pub fn new(spec: &Specs) -> Box<dyn Oscillator> {
+    pub fn new(spec: &Specs, sample_rate: Hz) -> Box<dyn Oscillator> {
         match spec {
-            Specs::Basic(Basic::Sine) => Box::new(Sine),
-            Specs::Basic(Basic::Square) => Box::new(Square),
-            Specs::Basic(Basic::Saw) => Box::new(Saw),
-            Specs::Pulse(duty_cycle) => Box::new(Pulse::new(*duty_cycle)),
-            Specs::Mix { n_voices, detune_amount, specs, random_seed } =>
-                Box::new(mix::Mix::detuned(*n_voices, *detune_amount, *specs, *random_seed)),
+            Specs::Basic(Basic::Sine, _) => Box::new(Sine::new(sample_rate)),
+            Specs::Basic(Basic::Square, Quality::Naive) => Box::new(Square::new(sample_rate)),
+            Specs::Basic(Basic::Square, Quality::BandLimited) => Box::new(band_limited::Square::new(sample_rate)),
+            Specs::Basic(Basic::Saw, Quality::Naive) => Box::new(Saw::new(sample_rate)),
+            Specs::Basic(Basic::Saw, Quality::BandLimited) => Box::new(band_limited::Saw::new(sample_rate)),
+            Specs::Pulse(duty_cycle, Quality::Naive) => Box::new(Pulse::new(*duty_cycle, sample_rate)),
+            Specs::Pulse(duty_cycle, Quality::BandLimited) =>
+                Box::new(band_limited::Pulse::new(*duty_cycle, sample_rate)),
+            Specs::Mix { n_voices, detune_amount, specs, quality, random_seed, spread } =>
+                Box::new(mix::Mix::detuned(*n_voices, *detune_amount, *specs, *quality, *random_seed, sample_rate)
+                    .with_spread(*spread)),
+            Specs::Noise(color) => Box::new(Noise::new(*color, NOISE_SEED)),
+            Specs::Wavetable { table, position } =>
+                Box::new(Wavetable::new(table.clone(), *position, sample_rate)),
+            Specs::Fm { operators, algorithm, index, feedback } =>
+                Box::new(Fm::new(**operators, *algorithm, *index, *feedback, sample_rate)),
         }
     }
 }
 
 impl Default for Specs {
     fn default() -> Self {
-        Specs::Basic(Basic::Sine)
+        Specs::Basic(Basic::Sine, Quality::Naive)
     }
 }
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct MixVoiceView {
     pub tuning: Hz,
+    pub pan: Pan,
     pub oscillator: Box<View>,
 }
 
@@ -61,8 +134,20 @@ pub struct MixVoiceView {
 pub enum View {
     Sine, Saw, Square, Pulse(Proportion),
     Mix {
-        voices: Vec<MixVoiceView>
-    }
+        voices: Vec<MixVoiceView>,
+        thickness: Proportion,
+        level: Proportion,
+        spread: Proportion,
+    },
+    Noise(NoiseColor),
+    Wavetable {
+        position: Proportion,
+        n_frames: usize,
+    },
+    Fm {
+        algorithm: Algorithm,
+        index: Proportion,
+    },
 }
 
 impl Default for View {
@@ -0,0 +1,100 @@
+use super::*;
+
+const PINK_ROWS: u64 = 16;
+
+/// Noise derived by hashing the sample index, so it's reproducible for a given seed.
+/// Instead of a phase, the voice state counts samples.
+///
+/// Pink and brown use the Voss-McCartney method: rows of white noise are summed,
+/// row `k` holding its value for `2^k` samples. Weighting every row equally gives a
+/// 1/f spectrum, weighting row `k` by `2^(k/2)` gives 1/f^2.
+pub struct Noise {
+    color: NoiseColor,
+    seed: u64,
+}
+impl Noise {
+    pub fn new(color: NoiseColor, seed: u64) -> Noise {
+        Noise { color, seed }
+    }
+
+    fn white(&self, index: u64) -> Sample {
+        random(self.seed, index)
+    }
+
+    fn octave_rows(&self, index: u64, weight: fn(u64) -> f64) -> Sample {
+        let (sum, power) = (0..PINK_ROWS)
+            .map(|row| (weight(row), random(self.seed.wrapping_add(row + 1), index >> row)))
+            .fold((0., 0.), |(sum, power), (w, value)| (sum + w * value, power + w * w));
+        (sum / power.sqrt()).clamp(-1., 1.)
+    }
+}
+impl Oscillator for Noise {
+    fn restart(&self, _state: &mut [f64], _position: Proportion) {}
+
+    fn next_sample(&self, state: &mut [f64], _freq: Hz, _phase: Proportion) -> Sample {
+        let index = state[0] as u64;
+        state[0] += 1.;
+        match self.color {
+            NoiseColor::White => self.white(index),
+            NoiseColor::Pink => self.octave_rows(index, |_| 1.),
+            NoiseColor::Brown => self.octave_rows(index, |row| 2_f64.powf(row as f64 / 2.)),
+        }
+    }
+
+    fn view(&self) -> View {
+        View::Noise(self.color)
+    }
+}
+impl Modulated<ModTarget> for Noise {
+    fn mod_param(&mut self, _target: ModTarget) -> Option<&mut ModParam> { None }
+}
+
+/// Uniform in [-1, 1)
+fn random(seed: u64, index: u64) -> Sample {
+    let bits = hash(hash(seed) ^ index);
+    (bits >> 11) as f64 / (1_u64 << 53) as f64 * 2. - 1.
+}
+
+/// https://xorshift.di.unimi.it/splitmix64.c
+fn hash(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(color: NoiseColor, seed: u64) -> Vec<Sample> {
+        let noise = Noise::new(color, seed);
+        let mut state = [0.];
+        (0..4096).map(|_| noise.next_sample(&mut state, 440., 0.)).collect()
+    }
+
+    #[test]
+    fn reproducible() {
+        for color in [NoiseColor::White, NoiseColor::Pink, NoiseColor::Brown].iter() {
+            assert_eq!(render(*color, 7), render(*color, 7));
+            assert_ne!(render(*color, 7), render(*color, 8));
+        }
+    }
+
+    #[test]
+    fn bounded() {
+        for color in [NoiseColor::White, NoiseColor::Pink, NoiseColor::Brown].iter() {
+            assert!(render(*color, 0).iter().all(|s| (-1. ..=1.).contains(s)));
+        }
+    }
+
+    #[test]
+    fn darker_colors_change_slower() {
+        let mean_step = |samples: Vec<Sample>| samples.windows(2)
+            .map(|w| (w[1] - w[0]).abs()).sum::<f64>() / samples.len() as f64;
+        let white = mean_step(render(NoiseColor::White, 0));
+        let pink = mean_step(render(NoiseColor::Pink, 0));
+        let brown = mean_step(render(NoiseColor::Brown, 0));
+        assert!(white > pink && pink > brown, "white: {}, pink: {}, brown: {}", white, pink, brown);
+    }
+}
@@ -1,18 +1,18 @@
-
 use super::*;
 
 pub struct Pulse {
     duty_cycle: ModParam,
+    sample_rate: Hz,
 }
 impl Pulse {
-    pub fn new(duty_cycle: Proportion) -> Pulse {
-        Pulse { duty_cycle: ModParam::with_base(duty_cycle, 0., 1.) }
+    pub fn new(duty_cycle: Proportion, sample_rate: Hz) -> Pulse {
+        Pulse { duty_cycle: ModParam::with_base(duty_cycle, 0., 1.), sample_rate }
     }
 }
 impl Oscillator for Pulse {
-    fn next_sample(&self, clock: Seconds, freq: Hz, phase: Seconds) -> Sample {
+    fn next_sample(&self, state: &mut [f64], freq: Hz, phase: Proportion) -> Sample {
         let duty_cycle = self.duty_cycle.calculate();
-        if ((clock + phase) * freq) % 1. < duty_cycle {1.} else {-1.}
+        if next_position(&mut state[0], freq, self.sample_rate, phase) < duty_cycle {1.} else {-1.}
     }
 
     fn view(&self) -> View {
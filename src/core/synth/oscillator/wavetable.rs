@@ -0,0 +1,221 @@
+use super::*;
+use crate::util::fft;
+use std::{f64::consts::PI, fmt, sync::Arc};
+
+/// Samples per frame at the highest resolution mip level
+const FRAME_SIZE: usize = 2048;
+/// Each level halves the frame size and the number of harmonics, down to 4 samples
+const N_LEVELS: usize = 10;
+
+///
+/// A set of single-cycle frames to morph between.
+/// Each frame is stored as a mip map with one band limited copy per octave,
+/// so high notes read from a copy with fewer harmonics instead of aliasing.
+///
+#[derive(Clone, PartialEq)]
+pub struct Table {
+    frames: Arc<Vec<MipMap>>,
+}
+
+type MipMap = Vec<Vec<Sample>>;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BuiltInTable {
+    /// From a sine to a full saw, doubling the number of harmonics at each frame
+    SawHarmonics,
+    /// Pulse with duty cycle narrowing from 50% to 5%
+    PulseWidth,
+    /// A single sine harmonic per frame, from the 1st to the 16th
+    SineHarmonics,
+}
+
+impl Table {
+    /// Frames can have any length, they're resampled to `FRAME_SIZE`
+    pub fn new(frames: Vec<Vec<Sample>>) -> Result<Table, String> {
+        if frames.is_empty() {
+            return Err("A wavetable needs at least one frame".to_string());
+        }
+        let resampled = frames.iter().enumerate()
+            .map(|(i, f)| resample(f).ok_or_else(|| format!("Wavetable frame {} is empty", i)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Table { frames: Arc::new(resampled.iter().map(|f| mip_map(f)).collect()) })
+    }
+
+    pub fn built_in(table: BuiltInTable) -> Table {
+        let frames = match table {
+            BuiltInTable::SawHarmonics => (0..8)
+                .map(|i| additive(1 << i, |n| 1. / n as f64))
+                .collect(),
+            BuiltInTable::PulseWidth => (0..16)
+                .map(|i| 0.5 - 0.45 * i as f64 / 15.)
+                .map(|duty| (0..FRAME_SIZE).map(|i| if (i as f64) < duty * FRAME_SIZE as f64 {1.} else {-1.}).collect())
+                .collect(),
+            BuiltInTable::SineHarmonics => (1..=16)
+                .map(|harmonic| additive(harmonic, |n| if n == harmonic {1.} else {0.}))
+                .collect(),
+        };
+        Table::new(frames).expect("Built-in tables have frames")
+    }
+
+    pub fn n_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn read(&self, frame: usize, level: usize, position: Proportion) -> Sample {
+        let samples = &self.frames[frame][level];
+        let index = position * samples.len() as f64;
+        let (i, fraction) = (index.floor() as usize % samples.len(), index.fract());
+        let next = samples[(i + 1) % samples.len()];
+        samples[i] + (next - samples[i]) * fraction
+    }
+}
+
+impl fmt::Debug for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Table({} frames)", self.frames.len())
+    }
+}
+
+pub struct Wavetable {
+    table: Table,
+    position: ModParam,
+    sample_rate: Hz,
+}
+impl Wavetable {
+    pub fn new(table: Table, position: Proportion, sample_rate: Hz) -> Wavetable {
+        Wavetable { table, position: ModParam::with_base(position, 0., 1.), sample_rate }
+    }
+
+    /// Lowest level whose highest harmonic stays below Nyquist
+    fn mip_level(&self, freq: Hz) -> usize {
+        let ratio = FRAME_SIZE as f64 * freq / self.sample_rate;
+        (ratio.log2().ceil().max(0.) as usize).min(N_LEVELS - 1)
+    }
+}
+impl Oscillator for Wavetable {
+    fn next_sample(&self, state: &mut [f64], freq: Hz, phase: Proportion) -> Sample {
+        let cycle_position = next_position(&mut state[0], freq, self.sample_rate, phase);
+        let level = self.mip_level(freq);
+        let frame_index = self.position.calculate() * (self.table.n_frames() - 1) as f64;
+        let (frame, fraction) = (frame_index.floor() as usize, frame_index.fract());
+        let current = self.table.read(frame, level, cycle_position);
+        if fraction > 0. {
+            let next = self.table.read(frame + 1, level, cycle_position);
+            current + (next - current) * fraction
+        } else {
+            current
+        }
+    }
+
+    fn view(&self) -> View {
+        View::Wavetable {
+            position: self.position.normalized(),
+            n_frames: self.table.n_frames(),
+        }
+    }
+}
+impl Modulated<ModTarget> for Wavetable {
+    fn mod_param(&mut self, target: ModTarget) -> Option<&mut ModParam> {
+        match target {
+            ModTarget::WavetablePosition => Some(&mut self.position),
+            _ => None
+        }
+    }
+}
+
+/// A frame of `FRAME_SIZE` samples summing sine harmonics `1..=n_harmonics`, normalized to peak at 1
+fn additive(n_harmonics: usize, amplitude: impl Fn(usize) -> f64) -> Vec<Sample> {
+    let frame: Vec<Sample> = (0..FRAME_SIZE)
+        .map(|i| 2. * PI * i as f64 / FRAME_SIZE as f64)
+        .map(|angle| (1..=n_harmonics).map(|n| amplitude(n) * (angle * n as f64).sin()).sum())
+        .collect();
+    let peak = frame.iter().fold(0., |max: f64, s| max.max(s.abs()));
+    frame.into_iter().map(|s| s / peak).collect()
+}
+
+/// None for an empty frame
+fn resample(frame: &[Sample]) -> Option<Vec<Sample>> {
+    if frame.is_empty() {
+        return None
+    }
+    if frame.len() == FRAME_SIZE {
+        return Some(frame.to_vec())
+    }
+    let resampled = (0..FRAME_SIZE)
+        .map(|i| i as f64 * frame.len() as f64 / FRAME_SIZE as f64)
+        .map(|index| {
+            let (i, fraction) = (index.floor() as usize, index.fract());
+            let next = frame[(i + 1) % frame.len()];
+            frame[i] + (next - frame[i]) * fraction
+        }).collect();
+    Some(resampled)
+}
+
+/// Level `n` has `FRAME_SIZE >> n` samples, keeping only the harmonics that fit below its Nyquist
+fn mip_map(frame: &[Sample]) -> MipMap {
+    let mut spectrum_re = frame.to_vec();
+    let mut spectrum_im = vec![0.; FRAME_SIZE];
+    fft::fft(&mut spectrum_re, &mut spectrum_im);
+    (0..N_LEVELS).map(|level| {
+        let size = FRAME_SIZE >> level;
+        let scale = size as f64 / FRAME_SIZE as f64;
+        let mut re = vec![0.; size];
+        let mut im = vec![0.; size];
+        for bin in 0..size / 2 {
+            re[bin] = spectrum_re[bin] * scale;
+            im[bin] = spectrum_im[bin] * scale;
+            if bin > 0 {
+                re[size - bin] = spectrum_re[FRAME_SIZE - bin] * scale;
+                im[size - bin] = spectrum_im[FRAME_SIZE - bin] * scale;
+            }
+        }
+        fft::ifft(&mut re, &mut im);
+        re
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: Hz = 44100.;
+
+    fn render(oscillator: &dyn Oscillator, freq: Hz) -> Vec<Sample> {
+        let mut state = [0.];
+        (0..512).map(|_| oscillator.next_sample(&mut state, freq, 0.)).collect()
+    }
+
+    #[test]
+    fn first_frame_of_saw_harmonics_is_a_sine() {
+        let oscillator = Wavetable::new(Table::built_in(BuiltInTable::SawHarmonics), 0., SAMPLE_RATE);
+        let sine = render(&basic::Sine::new(SAMPLE_RATE), 220.);
+        for (i, (sample, expected)) in render(&oscillator, 220.).into_iter().zip(sine).enumerate() {
+            assert!((sample - expected).abs() < 1e-3, "{}: {} != {}", i, sample, expected);
+        }
+    }
+
+    #[test]
+    fn morphs_between_frames() {
+        let table = Table::new(vec![vec![0.; 4], vec![1.; 4]]).unwrap();
+        let mut oscillator = Wavetable::new(table, 0.25, SAMPLE_RATE);
+        assert!(render(&oscillator, 100.).iter().all(|s| (s - 0.25).abs() < 1e-9));
+        oscillator.mod_param(ModTarget::WavetablePosition).unwrap().set_base(1.);
+        assert!(render(&oscillator, 100.).iter().all(|s| (s - 1.).abs() < 1e-9));
+    }
+
+    #[test]
+    fn higher_notes_read_levels_with_fewer_harmonics() {
+        let oscillator = Wavetable::new(Table::built_in(BuiltInTable::SawHarmonics), 1., SAMPLE_RATE);
+        assert_eq!(oscillator.mip_level(20.), 0);
+        let level = oscillator.mip_level(5000.);
+        let highest_harmonic = (FRAME_SIZE >> level) / 2;
+        assert!(highest_harmonic as f64 * 5000. <= SAMPLE_RATE / 2.);
+        assert!(highest_harmonic as f64 * 2. * 5000. > SAMPLE_RATE / 2.);
+    }
+
+    #[test]
+    fn rejects_empty_frames() {
+        assert!(Table::new(vec![]).is_err());
+        assert!(Table::new(vec![vec![1.; 4], vec![]]).is_err());
+    }
+}
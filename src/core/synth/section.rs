@@ -0,0 +1,270 @@
+//! Layers of oscillators playing the same note, each with its own tuning, level and pan,
+//! plus an optional sub oscillator. Layer 2 can be hard synced to and/or ring modulated by layer 1.
+
+use super::{Frame, Proportion, Pan, oscillator::{self, Oscillator, Basic, Quality}, modulated::*};
+use crate::core::music_theory::{Hz, Octave, Semitones};
+
+pub const MAX_LAYERS: usize = 3;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Specs {
+    pub layers: Vec<Layer>,
+    pub sub: Option<Sub>,
+    /// Layer 2 restarts its cycle whenever layer 1 does
+    pub sync: bool,
+    /// Layer 2 is multiplied by layer 1
+    pub ring: bool,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Layer {
+    pub oscillator: oscillator::Specs,
+    pub octave: Octave,
+    pub semitones: Semitones,
+    pub cents: f64,
+    pub level: Proportion,
+    pub pan: Pan,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Sub {
+    pub shape: Basic,
+    /// Relative to the note, usually -1 or -2
+    pub octave: Octave,
+    pub level: Proportion,
+}
+
+impl Specs {
+    pub fn single(oscillator: oscillator::Specs) -> Specs {
+        Specs { layers: vec![Layer::new(oscillator)], sub: None, sync: false, ring: false }
+    }
+}
+
+impl Layer {
+    pub fn new(oscillator: oscillator::Specs) -> Layer {
+        Layer { oscillator, octave: 0, semitones: 0, cents: 0., level: 1., pan: 0. }
+    }
+
+    fn freq_ratio(&self) -> f64 {
+        let semitones = f64::from(self.octave) * 12. + f64::from(self.semitones) + self.cents / 100.;
+        2_f64.powf(semitones / 12.)
+    }
+}
+
+pub struct Section {
+    layers: Vec<PlayingLayer>,
+    sub: Option<PlayingSub>,
+    sync: bool,
+    ring: bool,
+    sample_rate: Hz,
+}
+
+struct PlayingLayer {
+    specs: Layer,
+    oscillator: Box<dyn Oscillator>,
+    freq_ratio: f64,
+}
+
+struct PlayingSub {
+    specs: Sub,
+    oscillator: Box<dyn Oscillator>,
+}
+
+impl Section {
+    pub fn new(specs: &Specs, sample_rate: Hz) -> Result<Section, String> {
+        if specs.layers.is_empty() || specs.layers.len() > MAX_LAYERS {
+            return Err(format!("Expected 1 to {} layers, got {}", MAX_LAYERS, specs.layers.len()));
+        }
+        Ok(Section {
+            layers: specs.layers.iter().map(|layer| PlayingLayer {
+                specs: layer.clone(),
+                oscillator: <dyn Oscillator>::new(&layer.oscillator, sample_rate),
+                freq_ratio: layer.freq_ratio(),
+            }).collect(),
+            sub: specs.sub.map(|sub| PlayingSub {
+                specs: sub,
+                oscillator: <dyn Oscillator>::new(&oscillator::Specs::Basic(sub.shape, Quality::Naive), sample_rate),
+            }),
+            sync: specs.sync,
+            ring: specs.ring,
+            sample_rate,
+        })
+    }
+
+    /// The master cycle used for sync, then the state of each layer and of the sub
+    pub fn state_size(&self) -> usize {
+        1 + self.layers.iter().map(|layer| layer.oscillator.state_size()).sum::<usize>()
+            + self.sub.as_ref().map(|sub| sub.oscillator.state_size()).unwrap_or(0)
+    }
+
+    pub fn next_sample(&self, state: &mut [f64], freq: Hz, phase: Proportion) -> Frame {
+        let (sync_cycle, mut rest) = state.split_first_mut().expect("missing section state");
+        let master_freq = freq * self.layers[0].freq_ratio;
+        let master_position = oscillator::next_position(sync_cycle, master_freq, self.sample_rate, 0.);
+        let mut master_frame = Frame::default();
+        let mut mix = Frame::default();
+        for (i, layer) in self.layers.iter().enumerate() {
+            let (layer_state, tail) = std::mem::take(&mut rest).split_at_mut(layer.oscillator.state_size());
+            rest = tail;
+            let layer_freq = freq * layer.freq_ratio;
+            let frame = if i == 1 && self.sync {
+                let frame = layer.oscillator.next_frame(layer_state, layer_freq, 0.);
+                if *sync_cycle < master_position {
+                    layer.oscillator.restart(layer_state, *sync_cycle * layer_freq / master_freq);
+                }
+                frame
+            } else {
+                layer.oscillator.next_frame(layer_state, layer_freq, phase)
+            };
+            let frame = if i == 1 && self.ring {
+                Frame::new(frame.left * master_frame.left, frame.right * master_frame.right)
+            } else { frame };
+            if i == 0 {
+                master_frame = frame;
+            }
+            mix += frame.balance(layer.specs.pan) * layer.specs.level;
+        }
+        let sub_sample = self.sub.as_ref().map(|sub| {
+            let sub_freq = freq * 2_f64.powi(i32::from(sub.specs.octave));
+            sub.oscillator.next_sample(rest, sub_freq, phase) * sub.specs.level
+        });
+        mix + Frame::mono(sub_sample.unwrap_or(0.))
+    }
+
+    pub fn view(&self) -> View {
+        View {
+            layers: self.layers.iter().map(|layer| LayerView {
+                oscillator: layer.oscillator.view(),
+                octave: layer.specs.octave,
+                semitones: layer.specs.semitones,
+                cents: layer.specs.cents,
+                level: layer.specs.level,
+                pan: layer.specs.pan,
+            }).collect(),
+            sub: self.sub.as_ref().map(|sub| sub.specs),
+            sync: self.sync,
+            ring: self.ring,
+        }
+    }
+}
+
+/// Modulates the first layer that has the target
+impl Modulated<oscillator::ModTarget> for Section {
+    fn mod_param(&mut self, target: oscillator::ModTarget) -> Option<&mut ModParam> {
+        self.layers.iter_mut()
+            .find_map(|layer| layer.oscillator.mod_param(target))
+    }
+}
+
+impl Default for Specs {
+    fn default() -> Self {
+        Specs::single(oscillator::Specs::default())
+    }
+}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct View {
+    pub layers: Vec<LayerView>,
+    pub sub: Option<Sub>,
+    pub sync: bool,
+    pub ring: bool,
+}
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct LayerView {
+    pub oscillator: oscillator::View,
+    pub octave: Octave,
+    pub semitones: Semitones,
+    pub cents: f64,
+    pub level: Proportion,
+    pub pan: Pan,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::oscillator::{Specs::Basic as BasicSpecs, Basic::*, Quality::Naive};
+    use crate::core::synth::{Sample, Seconds};
+
+    const SAMPLE_RATE: Hz = 44100.;
+
+    fn assert_renders(section: &Section, expected: impl Fn(Seconds) -> Sample) {
+        let mut state = vec![0.; section.state_size()];
+        for i in 0..500 {
+            let clock = i as f64 / SAMPLE_RATE;
+            let frame = section.next_sample(&mut state, 220., 0.);
+            assert_eq!(frame.left, frame.right);
+            assert!((frame.left - expected(clock)).abs() < 1e-9, "{}: {} != {}", i, frame.left, expected(clock));
+        }
+    }
+
+    fn sine(clock: Seconds, freq: Hz) -> Sample {
+        (clock * freq * 2. * std::f64::consts::PI).sin()
+    }
+
+    #[test]
+    fn tuning_and_level() {
+        let mut layer = Layer::new(BasicSpecs(Sine, Naive));
+        layer.octave = 1;
+        layer.semitones = -12;
+        layer.cents = 1200.;
+        layer.level = 0.5;
+        let specs = Specs { layers: vec![layer], ..Default::default() };
+        assert_renders(&Section::new(&specs, SAMPLE_RATE).unwrap(), |clock| 0.5 * sine(clock, 440.));
+    }
+
+    #[test]
+    fn ring_and_sub() {
+        let specs = Specs {
+            layers: vec![Layer::new(BasicSpecs(Sine, Naive)), Layer::new(BasicSpecs(Sine, Naive))],
+            sub: Some(Sub { shape: Sine, octave: -1, level: 1. }),
+            sync: false,
+            ring: true,
+        };
+        assert_renders(&Section::new(&specs, SAMPLE_RATE).unwrap(),
+                       |clock| sine(clock, 220.) + sine(clock, 220.).powi(2) + sine(clock, 110.));
+    }
+
+    #[test]
+    fn pans_layers() {
+        let mut left = Layer::new(BasicSpecs(Sine, Naive));
+        left.pan = -1.;
+        let mut right = Layer::new(BasicSpecs(Sine, Naive));
+        right.octave = 1;
+        right.pan = 0.5;
+        let section = Section::new(&Specs { layers: vec![left, right], ..Default::default() }, SAMPLE_RATE).unwrap();
+        let mut state = vec![0.; section.state_size()];
+        for i in 0..500 {
+            let clock = i as f64 / SAMPLE_RATE;
+            let frame = section.next_sample(&mut state, 220., 0.);
+            assert!((frame.left - sine(clock, 220.) - 0.5 * sine(clock, 440.)).abs() < 1e-9);
+            assert!((frame.right - sine(clock, 440.)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn sync_restarts_layer_2() {
+        let mut master = Layer::new(BasicSpecs(Sine, Naive));
+        master.level = 0.;
+        let mut slave = Layer::new(BasicSpecs(Saw, Naive));
+        slave.semitones = 7;
+        let specs = |sync| Specs { layers: vec![master.clone(), slave.clone()], sync, ..Default::default() };
+        let ratio = 2_f64.powf(7. / 12.);
+        let synced_saw = move |clock: Seconds| ((clock * 220.) % 1. * ratio) % 1.;
+        assert_renders(&Section::new(&specs(true), SAMPLE_RATE).unwrap(), synced_saw);
+        let free = Section::new(&specs(false), SAMPLE_RATE).unwrap();
+        let mut state = vec![0.; free.state_size()];
+        let max_difference = (0..500)
+            .map(|i| (free.next_sample(&mut state, 220., 0.).left - synced_saw(i as f64 / SAMPLE_RATE)).abs())
+            .fold(0., f64::max);
+        assert!(max_difference > 0.1);
+    }
+
+    #[test]
+    fn rejects_layer_counts() {
+        let specs = |count| Specs { layers: vec![Layer::new(BasicSpecs(Sine, Naive)); count], ..Default::default() };
+        assert!(Section::new(&specs(0), SAMPLE_RATE).is_err());
+        assert!(Section::new(&specs(MAX_LAYERS), SAMPLE_RATE).is_ok());
+        assert!(Section::new(&specs(MAX_LAYERS + 1), SAMPLE_RATE).is_err());
+    }
+}
@@ -7,12 +7,17 @@ use crate::core::music_theory::{diatonic_scale::*, pitch::Pitch, rhythm::Note};
 pub mod builder;
 pub mod phrase;
 
+/// Notes starting within one tick before the buffer has to grow
+const NOTES_CAPACITY: usize = 16;
+
 pub struct Arpeggiator {
     phrase: Phrase,
     pub key: Key,
     holding_pitch: Option<Pitch>,
     playing_pitch: Option<Pitch>,
     pending_command: Option<Command>,
+    /// Reused on every tick to avoid allocating
+    notes: Vec<Note>,
 }
 
 #[derive(Clone, PartialEq, Default, Debug)]
@@ -48,6 +53,7 @@ impl Arpeggiator {
             holding_pitch: None,
             playing_pitch: None,
             pending_command: None,
+            notes: Vec::with_capacity(NOTES_CAPACITY),
         }
     }
 
@@ -75,31 +81,33 @@ impl Arpeggiator {
         self.holding_pitch.map(|p| p == id.pitch).unwrap_or(false)
     }
 
-    pub fn next(&mut self, from_measure: MeasurePosition, to_measure: MeasurePosition) -> Vec<Command> {
+    /// Appends the commands due between the two positions
+    pub fn next(&mut self, from_measure: MeasurePosition, to_measure: MeasurePosition, commands: &mut Vec<Command>) {
         match mem::replace(&mut self.pending_command, None) {
-            Some(pending) => vec![pending],
-            None => self.next_notes(from_measure, to_measure).iter()
-                        .flat_map(|e| self.update_and_command(e)).collect()
+            Some(pending) => commands.push(pending),
+            None => {
+                let mut notes = mem::take(&mut self.notes);
+                notes.clear();
+                self.phrase.for_each_in_range(from_measure, to_measure, |note| notes.push(*note));
+                notes.iter().for_each(|note| self.update_and_command(note, commands));
+                self.notes = notes;
+            }
         }
     }
 
-    fn next_notes(&mut self, from_measure: MeasurePosition, to_measure: MeasurePosition) -> Vec<Note> {
-        self.phrase.range(from_measure, to_measure)
-    }
-
-    fn update_and_command(&mut self, note: &Note) -> Vec<Command> {
+    fn update_and_command(&mut self, note: &Note, commands: &mut Vec<Command>) {
         match (self.holding_pitch, self.playing_pitch) {
             (Some(holding), None) =>
-                self.update_note_on(note.pitch, holding).into_iter().collect(),
-            (Some(holding), Some(playing)) =>
-                vec![ Some(note_off(playing)),
-                      self.update_note_on(note.pitch, holding),
-                ].into_iter().flatten().collect(),
+                commands.extend(self.update_note_on(note.pitch, holding)),
+            (Some(holding), Some(playing)) => {
+                commands.push(note_off(playing));
+                commands.extend(self.update_note_on(note.pitch, holding));
+            },
             (None, Some(playing)) => {
                 self.playing_pitch = None;
-                vec![note_off(playing)]
+                commands.push(note_off(playing));
             }
-            _ => vec![],
+            _ => (),
         }
     }
 
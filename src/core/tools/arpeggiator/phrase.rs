@@ -34,6 +34,10 @@ impl Phrase {
         self.map.range(from, to).into_iter().cloned().collect()
     }
 
+    pub fn for_each_in_range(&self, from: f64, to: f64, f: impl FnMut(&Note)) {
+        self.map.for_each_in_range(from, to, f)
+    }
+
     pub fn view(&self) -> View {
         View {
             notes: self.map.full_cycle().into_iter().cloned().collect(),
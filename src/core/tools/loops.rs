@@ -1,25 +1,49 @@
-use crate::core::synth::Sample;
-use std::{collections::HashMap, mem};
+use crate::core::{music_theory::Hz, synth::{Frame, Seconds}};
+use std::{collections::HashMap, mem, thread};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+
+/// Longest recording unless set with `Manager::with_max_length`
+pub const DEFAULT_MAX_LENGTH: Seconds = 30.;
+/// Loops kept at once before the map has to grow
+const LOOPS_CAPACITY: usize = 16;
+/// Jobs queued for the buffer thread before sending has to wait
+const JOBS_CAPACITY: usize = 16;
 
 #[derive(Clone, Copy)]
 pub enum Command { TogglePlayback(usize), ToggleRecording(usize) }
 
-#[derive(Default)]
 pub struct Manager {
     loops: HashMap<usize, Loop>,
-    playing_loops: HashMap<usize, Playback>,
     recording_loop: Option<Recorder>,
+    buffers: Buffers,
 }
 
 #[derive(Clone, PartialEq, Default, Debug)]
 pub struct View {
     pub playing_loops: HashMap<usize, bool>,
     pub recording_loop: Option<usize>,
+    /// The recording reached its max length and stopped taking frames
+    pub recording_full: bool,
 }
 
 impl Manager {
 
+    pub fn new(sample_rate: Hz) -> Manager {
+        Manager::with_max_length(sample_rate, DEFAULT_MAX_LENGTH)
+    }
+
+    /// Recordings stop taking frames after `max_length`, so their buffers can be reserved up front
+    pub fn with_max_length(sample_rate: Hz, max_length: Seconds) -> Manager {
+        assert!(max_length > 0., "max_length was: {}", max_length);
+        Manager {
+            loops: HashMap::with_capacity(LOOPS_CAPACITY),
+            recording_loop: None,
+            buffers: Buffers::new((max_length * sample_rate) as usize),
+        }
+    }
+
     pub fn interpret(&mut self, command: Command) {
+        self.receive_loops();
         match command {
             Command::TogglePlayback(i) => self.toggle_playback(i),
             Command::ToggleRecording(i) => self.toggle_recording(i),
@@ -28,80 +52,214 @@ impl Manager {
 
     fn toggle_recording(&mut self, index: usize) {
         if let Some(recorder) = mem::replace(&mut self.recording_loop, None) {
-            let new_loop = recorder.stop_recording();
-            self.loops.insert(index, new_loop);
+            // Plays silence until the trimmed frames are back
+            if let Some(replaced) = self.loops.insert(index, Loop { frames: Vec::new(), position: None }) {
+                self.buffers.send(Job::Free(replaced.frames));
+            }
+            self.buffers.send(Job::Trim(index, recorder.frames));
         } else {
-            self.recording_loop = Some(Recorder::new(index))
+            self.recording_loop = Some(Recorder::new(index, self.buffers.take()))
         }
     }
 
-    fn toggle_playback(&mut self, index: usize) {
-        if self.playing_loops.remove(&index).is_none() {
-            if let Some(loop_to_play) = self.loops.get(&index) {
-                self.playing_loops.insert(index, loop_to_play.start_playback());
+    /// Loops come back once their buffers are trimmed
+    fn receive_loops(&mut self) {
+        self.buffers.send_pending();
+        while let Some((index, frames)) = self.buffers.trimmed() {
+            if let Some(l) = self.loops.get_mut(&index) {
+                let replaced = mem::replace(&mut l.frames, frames);
+                self.buffers.send(Job::Free(replaced));
             }
         }
     }
 
-    pub fn write(&mut self, sample: Sample) {
+    fn toggle_playback(&mut self, index: usize) {
+        if let Some(loop_to_toggle) = self.loops.get_mut(&index) {
+            loop_to_toggle.toggle_playback();
+        }
+    }
+
+    pub fn write(&mut self, frame: Frame) {
         if let Some(rec) = self.recording_loop.as_mut() {
-            rec.write(sample)
+            rec.write(frame)
         }
     }
 
-    pub fn next_sample(&mut self) -> Sample {
-        self.playing_loops.values_mut()
-            .filter_map(|l| l.next())
+    pub fn next_frame(&mut self) -> Frame {
+        self.receive_loops();
+        self.loops.values_mut()
+            .filter_map(Loop::next_frame)
             .sum()
     }
 
     pub fn view(&self) -> View {
         View {
-            playing_loops: self.loops.keys().map(|k| (*k, self.playing_loops.contains_key(k))).collect(),
+            playing_loops: self.loops.iter().map(|(k, l)| (*k, l.position.is_some())).collect(),
             recording_loop: self.recording_loop.as_ref().map(|l| l.position),
+            recording_full: self.recording_loop.as_ref().map(Recorder::is_full).unwrap_or(false),
         }
     }
 }
 
 struct Loop {
-    samples: Vec<Sample>
+    frames: Vec<Frame>,
+    /// Where playback is, if playing
+    position: Option<usize>,
 }
 impl Loop {
-    fn start_playback(&self) -> Playback {
-        Playback::new(self.samples.to_vec())
+    fn toggle_playback(&mut self) {
+        self.position = match self.position {
+            Some(_) => None,
+            None => Some(0),
+        };
+    }
+
+    fn next_frame(&mut self) -> Option<Frame> {
+        let position = self.position?;
+        self.position = Some((position + 1) % self.frames.len().max(1));
+        self.frames.get(position).copied()
     }
 }
 
 struct Recorder {
     position: usize,
-    samples: Vec<Sample>,
+    frames: Vec<Frame>,
 }
 impl Recorder {
-    fn new(position: usize) -> Recorder {
-        Recorder { position, samples: vec![] }
+    fn new(position: usize, frames: Vec<Frame>) -> Recorder {
+        Recorder { position, frames }
     }
-    fn write(&mut self, sample: Sample) {
-        self.samples.push(sample)
+    fn write(&mut self, frame: Frame) {
+        if !self.is_full() {
+            self.frames.push(frame)
+        }
     }
-    fn stop_recording(self) -> Loop {
-        Loop { samples: self.samples }
+    fn is_full(&self) -> bool {
+        self.frames.len() == self.frames.capacity()
     }
 }
 
-struct Playback {
-    position: usize,
-    samples: Vec<Sample>,
+/// Work for the buffer thread
+enum Job {
+    /// A finished recording for the loop at that index
+    Trim(usize, Vec<Frame>),
+    /// Frames no loop uses anymore
+    Free(Vec<Frame>),
+}
+
+/// Allocates recording buffers, trims finished ones and frees old ones on another thread, away from the audio
+struct Buffers {
+    max_frames: usize,
+    ready: Receiver<Vec<Frame>>,
+    jobs: SyncSender<Job>,
+    /// Jobs the thread had no room for yet, retried instead of waiting
+    pending: Vec<Job>,
+    trimmed: Receiver<(usize, Vec<Frame>)>,
 }
-impl Playback {
-    fn new(samples: Vec<Sample>) -> Playback {
-        Playback { position: 0, samples }
+impl Buffers {
+    fn new(max_frames: usize) -> Buffers {
+        let (ready_out, ready) = mpsc::sync_channel(1);
+        let (jobs, jobs_in) = mpsc::sync_channel(JOBS_CAPACITY);
+        let (trimmed_out, trimmed) = mpsc::sync_channel(1);
+        let _ = ready_out.send(Vec::with_capacity(max_frames));
+        thread::spawn(move || {
+            for job in jobs_in.iter() {
+                if let Job::Trim(index, mut frames) = job {
+                    frames.shrink_to_fit();
+                    if trimmed_out.send((index, frames)).is_err() {
+                        break;
+                    }
+                    // Still full if a recording started before this one came back and allocated its own
+                    let _ = ready_out.try_send(Vec::with_capacity(max_frames));
+                }
+            }
+        });
+        Buffers { max_frames, ready, jobs, pending: Vec::with_capacity(JOBS_CAPACITY), trimmed }
+    }
+
+    /// Allocates right away only if the previous buffer is still being replaced
+    fn take(&mut self) -> Vec<Frame> {
+        self.ready.try_recv().unwrap_or_else(|_| Vec::with_capacity(self.max_frames))
+    }
+
+    /// Never waits, jobs the thread can't take yet are kept for `send_pending`
+    fn send(&mut self, job: Job) {
+        if matches!(&job, Job::Free(frames) if frames.capacity() == 0) {
+            return;
+        }
+        self.send_pending();
+        if !self.pending.is_empty() {
+            self.pending.push(job);
+        } else if let Err(TrySendError::Full(job)) = self.jobs.try_send(job) {
+            self.pending.push(job);
+        }
+    }
+
+    fn send_pending(&mut self) {
+        while !self.pending.is_empty() {
+            let job = self.pending.remove(0);
+            if let Err(TrySendError::Full(job)) = self.jobs.try_send(job) {
+                self.pending.insert(0, job);
+                return;
+            }
+        }
+    }
+
+    fn trimmed(&mut self) -> Option<(usize, Vec<Frame>)> {
+        self.trimmed.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn wait_for_loop(manager: &mut Manager, index: usize) -> usize {
+        loop {
+            manager.receive_loops();
+            match manager.loops.get(&index) {
+                Some(l) if !l.frames.is_empty() => return l.frames.capacity(),
+                _ => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+    }
+
+    #[test]
+    fn trims_and_reports_full_recordings() {
+        let mut manager = Manager::with_max_length(10., 30.);
+        manager.interpret(Command::ToggleRecording(0));
+        (0..10).for_each(|_| manager.write(Frame::mono(1.)));
+        assert!(!manager.view().recording_full);
+        manager.interpret(Command::ToggleRecording(0));
+        assert_eq!(wait_for_loop(&mut manager, 0), 10);
+
+        manager.interpret(Command::ToggleRecording(1));
+        (0..1000).for_each(|_| manager.write(Frame::mono(1.)));
+        assert!(manager.view().recording_full);
+        manager.interpret(Command::ToggleRecording(1));
+        assert_eq!(wait_for_loop(&mut manager, 1), 300);
+
+        let mut manager = Manager::with_max_length(10., 60.);
+        manager.interpret(Command::ToggleRecording(0));
+        (0..1000).for_each(|_| manager.write(Frame::mono(1.)));
+        manager.interpret(Command::ToggleRecording(0));
+        assert_eq!(wait_for_loop(&mut manager, 0), 600);
+    }
+
+    #[test]
+    fn records_again_before_the_trim_is_back() {
+        let mut manager = Manager::new(10.);
+        for (index, length) in [(0, 5), (1, 6), (0, 7), (0, 8)] {
+            manager.interpret(Command::ToggleRecording(index));
+            (0..length).for_each(|_| manager.write(Frame::mono(1.)));
+            manager.interpret(Command::ToggleRecording(index));
+        }
+        manager.interpret(Command::TogglePlayback(0));
+        while wait_for_loop(&mut manager, 0) != 8 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(wait_for_loop(&mut manager, 1), 6);
+        assert_eq!(manager.next_frame(), Frame::mono(1.));
     }
 }
-impl Iterator for Playback {
-    type Item = Sample;
-    fn next(&mut self) -> Option<Sample> {
-        let position = self.position;
-        self.position = (self.position + 1) % self.samples.len();
-        self.samples.get(position).cloned()
-    }
-}
\ No newline at end of file
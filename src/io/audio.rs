@@ -4,10 +4,13 @@ use self::cpal::{
     StreamData::Output,
     OutputBuffer, Device, Format, EventLoop
 };
-use std::sync::mpsc::Receiver;
-use crate::core::{music_theory::Hz, synth::Sample, tools::Millis};
+use crate::core::{control::BLOCK_SIZE, music_theory::Hz, tools::Millis};
+use crate::util::ring_buffer::Consumer;
 
-const LATENCY: Millis = 250;
+/// How far the synth renders ahead of the sound card
+const LATENCY: Millis = 50;
+/// Room for the synth to write whole blocks even at very low latencies
+const MIN_BLOCKS: usize = 4;
 
 pub struct Out {
     device: Device,
@@ -30,15 +33,20 @@ impl Out {
     }
 
     pub fn buffer_size(&self) -> usize {
-        self.sample_rate() as usize / LATENCY as usize
+        buffer_size(self.sample_rate())
     }
 
-    pub fn start(&self, sound_in: Receiver<Sample>) {
+    pub fn start(&self, sound_in: Consumer) {
         start(&self.device, &self.format, sound_in)
     }
 }
 
-fn start(device: &Device, format: &Format, sound_in: Receiver<Sample>) {
+/// Frames held between the synth and the sound card
+fn buffer_size(sample_rate: Hz) -> usize {
+    ((sample_rate * LATENCY as f64 / 1000.) as usize).max(MIN_BLOCKS * BLOCK_SIZE)
+}
+
+fn start(device: &Device, format: &Format, mut sound_in: Consumer) {
     let channels = format.channels as usize;
     let event_loop = EventLoop::new();
     let stream_id = event_loop.build_output_stream(device, format).unwrap();
@@ -46,23 +54,28 @@ fn start(device: &Device, format: &Format, sound_in: Receiver<Sample>) {
 
     event_loop.run(move |_, data| {
         match data {
-            Output { buffer: F32(buffer) } => feed_buffer(buffer, &sound_in, channels),
-            Output { buffer: I16(buffer) } => feed_buffer(buffer, &sound_in, channels),
-            Output { buffer: U16(buffer) } => feed_buffer(buffer, &sound_in, channels),
+            Output { buffer: F32(buffer) } => feed_buffer(buffer, &mut sound_in, channels),
+            Output { buffer: I16(buffer) } => feed_buffer(buffer, &mut sound_in, channels),
+            Output { buffer: U16(buffer) } => feed_buffer(buffer, &mut sound_in, channels),
             _ => panic!("Unexpected buffer type."),
         }
     });
 }
 
-fn feed_buffer<T: SampleFromF64>(mut buffer: OutputBuffer<'_, T>, sig_in: &Receiver<Sample>, channels: usize) {
+/// Left and right go to the first two channels, any others get both mixed.
+/// Plays silence if the synth falls behind rather than blocking the callback.
+fn feed_buffer<T: SampleFromF64>(mut buffer: OutputBuffer<'_, T>, sig_in: &mut Consumer, channels: usize) {
     for buff_chunks in buffer.chunks_mut(channels) {
-        match sig_in.recv() {
-            Ok(sample) =>
-                for out in buff_chunks.iter_mut() {
-                    *out = T::from_f64(sample);
-                },
-            _ => {
-                panic!("Sample channel hang up?");
+        let frame = sig_in.pop().unwrap_or_default();
+        if channels == 1 {
+            buff_chunks[0] = T::from_f64(frame.to_mono());
+        } else {
+            for (i, out) in buff_chunks.iter_mut().enumerate() {
+                *out = T::from_f64(match i {
+                    0 => frame.left,
+                    1 => frame.right,
+                    _ => frame.to_mono(),
+                });
             }
         }
     }
@@ -86,3 +99,34 @@ impl SampleFromF64 for u16 {
         ((value * 0.5 + 0.5) * f64::from(u16::MAX)) as u16
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+    use crate::core::synth::Frame;
+    use crate::util::ring_buffer::ring_buffer;
+
+    #[test]
+    fn no_underruns_with_large_callbacks() {
+        let sample_rate = 44100.;
+        let callback_size = 1024;
+        let (mut producer, mut consumer) = ring_buffer(buffer_size(sample_rate));
+        let writer = thread::spawn(move || {
+            let block = [Frame::mono(0.5); BLOCK_SIZE];
+            while producer.push_all(&block).is_ok() {}
+        });
+        while consumer.len() < callback_size {
+            thread::yield_now();
+        }
+        let callback_period = Duration::from_secs_f64(callback_size as f64 / sample_rate);
+        let mut underruns = 0;
+        for _ in 0..20 {
+            underruns += (0..callback_size).filter(|_| consumer.pop().is_none()).count();
+            thread::sleep(callback_period);
+        }
+        drop(consumer);
+        writer.join().unwrap();
+        assert_eq!(underruns, 0);
+    }
+}
@@ -15,6 +15,11 @@ use self::rimd::{Event as RimdEvent, MidiMessage, SMF, SMFError,
 mod patch;
 mod meta_events;
 
+const MOD_WHEEL_CC: u8 = 1;
+const PAN_CC: u8 = 10;
+/// Data bytes are 7 bits
+const MAX_DATA: f64 = 127.;
+
 pub fn read_file(file_path: &str) -> Option<SheetMusic> {
     println!("MIDI: Reading file: {}", file_path);
     match SMF::from_file(Path::new(file_path)) {
@@ -112,6 +117,7 @@ fn accumulate_time(events: Vec<Event>) -> Vec<Event> {
         }).collect()
 }
 
+#[allow(clippy::large_enum_variant)] // Only kept while reading a file, Meta is the rare variant
 enum Event {
     Midi(ScheduledCommand, ChannelId),
     Meta(ScheduledMeta)
@@ -133,9 +139,15 @@ fn decode_event(event: &RimdTrackEvent) -> Option<Event> {
 
 fn decode_note_event(msg: &MidiMessage) -> Option<Command> {
     match msg.data.as_slice() {
+        [_, lsb, msb] if matches!(msg.status(), Status::PitchBend) =>
+            Some(PitchBend(decode_pitch_bend(*lsb, *msb))),
+        [_, MOD_WHEEL_CC, value] if matches!(msg.status(), Status::ControlChange) =>
+            Some(ModWheel(f64::from(*value) / MAX_DATA)),
+        [_, PAN_CC, value] if matches!(msg.status(), Status::ControlChange) =>
+            Some(Pan(decode_pan(*value))),
         [_, pitch_byte, velocity_byte] => {
             let pitch = Pitch::from_index(*pitch_byte as usize);
-            let velocity: f64 = *velocity_byte as f64 / u8::MAX as f64;
+            let velocity: f64 = f64::from(*velocity_byte) / MAX_DATA;
             let note_on = NoteOn(pitch, velocity, id(pitch));
             let note_off = NoteOff(id(pitch));
             match (msg.status(), *velocity_byte) {
@@ -148,9 +160,42 @@ fn decode_note_event(msg: &MidiMessage) -> Option<Command> {
         [_, byte] => {
             match msg.status() {
                 Status::ProgramChange => patch::decode(*byte).map(SetPatch),
+                Status::ChannelAftertouch => Some(Aftertouch(f64::from(*byte) / MAX_DATA)),
                 _ => None,
             }
         }
         _ => None,
     }
 }
+
+/// 14 bits centered at 0x2000
+fn decode_pitch_bend(lsb: u8, msb: u8) -> f64 {
+    let value = (i32::from(msb & 0x7f) << 7) | i32::from(lsb & 0x7f);
+    f64::from(value - 0x2000) / f64::from(0x2000)
+}
+
+/// 64 is the center, 0 hard left and 127 hard right
+fn decode_pan(value: u8) -> f64 {
+    ((f64::from(value) - 64.) / 63.).clamp(-1., 1.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pan_is_centered_at_64() {
+        assert_eq!(decode_pan(0), -1.);
+        assert_eq!(decode_pan(64), 0.);
+        assert_eq!(decode_pan(127), 1.);
+    }
+
+    #[test]
+    fn pitch_bend_is_centered_at_0x2000() {
+        assert_eq!(decode_pitch_bend(0x00, 0x40), 0.);
+        assert_eq!(decode_pitch_bend(0x00, 0x00), -1.);
+        assert_eq!(decode_pitch_bend(0x7f, 0x7f), f64::from(0x1fff) / f64::from(0x2000));
+        assert_eq!(decode_pitch_bend(0x01, 0x40), 1. / f64::from(0x2000));
+        assert_eq!(decode_pitch_bend(0x40, 0x01), f64::from(0xc0 - 0x2000) / f64::from(0x2000));
+    }
+}
@@ -10,10 +10,13 @@ pub fn decode(program_byte: u8) -> Option<Specs> {
 
 fn patch_to_specs(patch: Patch) -> Specs {
     match patch.category {
-        SynthLead | Piano | Guitar | Bass | SynthEffects | Ensemble => preset::supersaw(),
-        ChromaticPercussion => preset::pulse(),
+        SynthLead | Guitar | SynthEffects | Ensemble => preset::supersaw(),
+        Bass => preset::sub_bass(),
+        Piano => preset::electric_piano(),
+        ChromaticPercussion => preset::bell(),
         Organ | Reed | Pipe => preset::sine(),
         Strings | SynthPad => preset::saw_pad(),
+        Percussive | SoundEffects => preset::noise_hit(),
         _ => preset::sine(),
     }
 }
@@ -1,18 +1,20 @@
-use std::sync::mpsc::{Sender, SyncSender, Receiver};
+use std::sync::mpsc::{Sender, Receiver};
 use std::sync::mpsc;
 use std::thread;
 
 use crate::core::{control::{tools, sheet_music}};
-use crate::core::synth::Sample;
 use crate::io::audio::Out;
+use crate::util::ring_buffer::{self, Producer};
 
 pub mod midi;
 pub mod audio;
+pub mod wav;
+pub mod render;
 
-pub fn start_audio() -> (SyncSender<Sample>, f64){
+pub fn start_audio() -> (Producer, f64){
     let out = Out::initialize().unwrap_or_else(|e| panic!("Failed to initialize audio: {}", e));
     let sample_rate = out.sample_rate();
-    let (sound_out, sound_in) = mpsc::sync_channel::<Sample>(out.buffer_size());
+    let (sound_out, sound_in) = ring_buffer::ring_buffer(out.buffer_size());
     thread::spawn(move || out.start(sound_in));
     (sound_out, sample_rate)
 }
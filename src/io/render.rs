@@ -0,0 +1,115 @@
+use std::path::Path;
+use crate::core::control::sheet_music::{self, Stem};
+use crate::core::sheet_music::sheet_music::{ChannelId, SheetMusic};
+use crate::io::{midi, wav};
+
+///
+/// Renders to WAV files without a sound card, as fast as possible
+///
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Settings {
+    pub sample_rate: u32,
+    pub format: wav::Format,
+    /// One file per MIDI channel instead of a single mix
+    pub stems: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings { sample_rate: 44100, format: wav::Format::Int16, stems: false }
+    }
+}
+
+pub fn render_midi(midi_path: &str, wav_path: &str, settings: Settings) -> Result<Vec<String>, String> {
+    let music = midi::read_file(midi_path)
+        .ok_or_else(|| format!("Failed to load MIDI file: [{}]", midi_path))?;
+    render_music(music, wav_path, settings)
+}
+
+/// Returns the files written. Stems are named after `wav_path` plus the channel, e.g. `song-3.wav`.
+pub fn render_music(music: SheetMusic, wav_path: &str, settings: Settings) -> Result<Vec<String>, String> {
+    let channels: Vec<_> = music.voices.iter().map(|voice| voice.instrument_id).collect();
+    let file_paths: Vec<String> = if settings.stems {
+        let mut channels = channels;
+        channels.sort_unstable();
+        channels.dedup();
+        channels.into_iter().map(|channel| stem_path(wav_path, channel)).collect()
+    } else {
+        vec![wav_path.to_string()]
+    };
+    let mut writers = file_paths.iter()
+        .map(|path| wav::Writer::create(path, settings.sample_rate, settings.format))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut mix = vec![];
+    sheet_music::render(f64::from(settings.sample_rate), music, |stems: &[Stem]| {
+        if settings.stems {
+            writers.iter_mut().zip(stems).try_for_each(|(writer, (_, frames))| writer.write(frames))
+        } else {
+            mix.clear();
+            mix.resize(stems.first().map(|(_, frames)| frames.len()).unwrap_or(0), Default::default());
+            for (_, frames) in stems {
+                mix.iter_mut().zip(frames).for_each(|(mixed, frame)| *mixed += *frame);
+            }
+            writers[0].write(&mix)
+        }
+    })?;
+    writers.into_iter().try_for_each(wav::Writer::finalize)?;
+    Ok(file_paths)
+}
+
+fn stem_path(wav_path: &str, channel: ChannelId) -> String {
+    let path = Path::new(wav_path);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("render");
+    let file_name = match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => format!("{}-{}.{}", stem, channel, extension),
+        None => format!("{}-{}", stem, channel),
+    };
+    path.with_file_name(file_name).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use hound::WavReader;
+    use crate::core::control::synth::{Command::*, id};
+    use crate::core::music_theory::pitch::Pitch;
+    use crate::core::sheet_music::sheet_music::{Section, Voice};
+
+    fn music() -> SheetMusic {
+        let note = |index| {
+            let pitch = Pitch::from_index(index);
+            vec![(NoteOn(pitch, 1., id(pitch)), 0), (NoteOff(id(pitch)), 100)]
+        };
+        SheetMusic {
+            sections: vec![Section { tick_duration: Duration::from_millis(1), ..Default::default() }],
+            voices: vec![Voice::new(note(60), 1), Voice::new(note(64), 2)],
+            end: 100,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn renders_mix_and_stems() {
+        let wav_path = std::env::temp_dir().join(format!("rust_synth_render_{}.wav", std::process::id()));
+        let wav_path = wav_path.to_str().unwrap();
+        let settings = Settings { sample_rate: 8000, ..Default::default() };
+        let mix = render_music(music(), wav_path, settings).unwrap();
+        let stems = render_music(music(), wav_path, Settings { stems: true, ..settings }).unwrap();
+        assert_eq!(mix, vec![wav_path.to_string()]);
+        assert_eq!(stems, vec![stem_path(wav_path, 1), stem_path(wav_path, 2)]);
+        for path in mix.iter().chain(stems.iter()) {
+            let reader = WavReader::open(path).unwrap();
+            assert_eq!(reader.spec().sample_rate, 8000);
+            assert!(reader.duration() > 800, "{}: {}", path, reader.duration());
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn stem_paths() {
+        assert_eq!(stem_path("out/song.wav", 3), "out/song-3.wav");
+        assert_eq!(stem_path("song", 10), "song-10");
+    }
+}
@@ -0,0 +1,125 @@
+use std::{fs::File, io::BufWriter};
+use hound::{WavReader, WavWriter, WavSpec, SampleFormat};
+use crate::core::synth::{Frame, Sample, oscillator::Table};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Format { Int16, Int24, Float }
+
+impl Format {
+    fn bits(self) -> u16 {
+        match self {
+            Format::Int16 => 16,
+            Format::Int24 => 24,
+            Format::Float => 32,
+        }
+    }
+}
+
+/// Stereo WAV file, written a block at a time
+pub struct Writer {
+    writer: WavWriter<BufWriter<File>>,
+    format: Format,
+    file_path: String,
+}
+
+impl Writer {
+    pub fn create(file_path: &str, sample_rate: u32, format: Format) -> Result<Writer, String> {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate,
+            bits_per_sample: format.bits(),
+            sample_format: if format == Format::Float { SampleFormat::Float } else { SampleFormat::Int },
+        };
+        WavWriter::create(file_path, spec)
+            .map(|writer| Writer { writer, format, file_path: file_path.to_string() })
+            .map_err(|e| format!("Failed to create WAV file: [{}]. {}", file_path, e))
+    }
+
+    /// Clips anything beyond full scale
+    pub fn write(&mut self, frames: &[Frame]) -> Result<(), String> {
+        let full_scale = ((1_i64 << (self.format.bits() - 1)) - 1) as f64;
+        for sample in frames.iter().flat_map(|frame| [frame.left, frame.right]) {
+            let sample = sample.clamp(-1., 1.);
+            let written = match self.format {
+                Format::Float => self.writer.write_sample(sample as f32),
+                Format::Int16 | Format::Int24 => self.writer.write_sample((sample * full_scale).round() as i32),
+            };
+            written.map_err(|e| format!("Failed to write WAV file: [{}]. {}", self.file_path, e))?;
+        }
+        Ok(())
+    }
+
+    pub fn finalize(self) -> Result<(), String> {
+        let file_path = self.file_path;
+        self.writer.finalize()
+            .map_err(|e| format!("Failed to write WAV file: [{}]. {}", file_path, e))
+    }
+}
+
+/// Builds a wavetable with one frame per file, in the given order.
+/// Each file is expected to hold a single cycle; multiple channels are mixed down.
+pub fn read_wavetable(file_paths: &[&str]) -> Result<Table, String> {
+    let frames = file_paths.iter()
+        .map(|path| read_single_cycle(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    Table::new(frames)
+}
+
+fn read_single_cycle(file_path: &str) -> Result<Vec<Sample>, String> {
+    let mut reader = WavReader::open(file_path)
+        .map_err(|e| format!("Failed to open WAV file: [{}]. {}", file_path, e))?;
+    let spec = reader.spec();
+    let interleaved: Result<Vec<Sample>, _> = match spec.sample_format {
+        SampleFormat::Float => reader.samples::<f32>()
+            .map(|s| s.map(f64::from))
+            .collect(),
+        SampleFormat::Int => {
+            let full_scale = (1_i64 << (spec.bits_per_sample - 1)) as f64;
+            reader.samples::<i32>()
+                .map(|s| s.map(|v| f64::from(v) / full_scale))
+                .collect()
+        },
+    };
+    let interleaved = interleaved.map_err(|e| format!("Failed to read WAV file: [{}]. {}", file_path, e))?;
+    let channels = spec.channels as usize;
+    let frame: Vec<Sample> = interleaved.chunks(channels)
+        .map(|c| c.iter().sum::<Sample>() / channels as f64)
+        .collect();
+    if frame.is_empty() {
+        Err(format!("WAV file has no samples: [{}]", file_path))
+    } else {
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_each_format() {
+        let frames = [Frame::new(0.5, -0.25), Frame::new(2., -1.)];
+        for format in [Format::Int16, Format::Int24, Format::Float] {
+            let file_path = std::env::temp_dir().join(format!("rust_synth_{:?}_{}.wav", format, std::process::id()));
+            let file_path = file_path.to_str().unwrap();
+            let mut writer = Writer::create(file_path, 44100, format).unwrap();
+            writer.write(&frames).unwrap();
+            writer.finalize().unwrap();
+
+            let mut reader = WavReader::open(file_path).unwrap();
+            assert_eq!(reader.spec().channels, 2);
+            assert_eq!(reader.spec().bits_per_sample, format.bits());
+            let samples: Vec<Sample> = match format {
+                Format::Float => reader.samples::<f32>().map(|s| f64::from(s.unwrap())).collect(),
+                _ => {
+                    let full_scale = ((1_i64 << (format.bits() - 1)) - 1) as f64;
+                    reader.samples::<i32>().map(|s| f64::from(s.unwrap()) / full_scale).collect()
+                },
+            };
+            let expected = [0.5, -0.25, 1., -1.];
+            assert_eq!(samples.len(), expected.len());
+            samples.iter().zip(expected.iter()).for_each(|(s, e)| assert!((s - e).abs() < 1e-4, "{:?}: {}", format, s));
+            std::fs::remove_file(file_path).unwrap();
+        }
+    }
+}
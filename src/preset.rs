@@ -3,10 +3,10 @@ use crate::core::{
         rhythm::{Note, NoteDuration::*},
         diatonic_scale::{ScaleDegree::*, OctaveShift::*}
     },
-    synth::{builder::*, lfo,
-            instrument::{self, ModTarget::*},
-            oscillator::{Basic::*, Specs::*, ModTarget::*},
-            filter::ModTarget::*
+    synth::{builder::*, lfo, adsr::{Dahdsr, Curve::*},
+            instrument::{self, ModTarget::*, ModSource, VelocityCurve, VoiceMode},
+            oscillator::{Basic::*, NoiseColor::*, Quality::*, Specs::*, ModTarget::*, Table, BuiltInTable, Operator, Algorithm},
+            filter::{self, ModTarget::*}
     },
     control::tools::Patch,
     tools::arpeggiator::phrase::Phrase,
@@ -19,6 +19,13 @@ pub fn instruments() -> Vec<instrument::Specs> {
         pulse(),
         sine(),
         saw_pad(),
+        noise_hit(),
+        wavetable_sweep(),
+        electric_piano(),
+        bell(),
+        sync_lead(),
+        sub_bass(),
+        pluck(),
     )
 }
 
@@ -32,10 +39,10 @@ pub fn sequences() -> Vec<Phrase> {
 
 pub fn patches() -> Vec<Patch> {
     vec!(
-        Patch::Instrument(supersaw()),
-        Patch::Instrument(pulse()),
-        Patch::Instrument(sine()),
-        Patch::Instrument(saw_pad()),
+        Patch::Instrument(Box::new(supersaw())),
+        Patch::Instrument(Box::new(pulse())),
+        Patch::Instrument(Box::new(sine())),
+        Patch::Instrument(Box::new(saw_pad())),
         Patch::Noop,
         Patch::Noop,
         Patch::ArpeggiatorPhrase(Some(cyborg_chase())),
@@ -46,20 +53,80 @@ pub fn patches() -> Vec<Patch> {
 }
 
 pub fn sine() -> instrument::Specs {
-    Builder::osc(Basic(Sine)).mod_y(Volume).build()
+    Builder::osc(Basic(Sine, Naive)).mod_y(Volume).build()
 }
 
 pub fn pulse() -> instrument::Specs {
-    Builder::osc(Pulse(0.5)).mod_y(Oscillator(PulseDuty)).build()
+    Builder::osc(Pulse(0.5, BandLimited)).mod_y(Oscillator(PulseDuty)).build()
 }
 
 pub fn saw_pad() -> instrument::Specs {
-    Builder::osc(Basic(Saw)).adsr(0.25, 0., 1., 0.25).build()
+    Builder::osc(Basic(Saw, BandLimited)).adsr(0.25, 0., 1., 0.25).build()
+}
+
+pub fn noise_hit() -> instrument::Specs {
+    Builder::osc(Noise(White)).adsr(0., 0.15, 0., 0.1).mod_y(Volume).build()
+}
+
+pub fn wavetable_sweep() -> instrument::Specs {
+    Builder::osc(Wavetable { table: Table::built_in(BuiltInTable::SawHarmonics), position: 0.5 })
+            .lfo(lfo::Specs::simple(0.2), Oscillator(WavetablePosition), 0.5)
+            .mod_y(Oscillator(WavetablePosition)).build()
+}
+
+pub fn electric_piano() -> instrument::Specs {
+    let operators = Box::new([
+        Operator { ratio: 1., level: 1. },
+        Operator { ratio: 1., level: 0.6 },
+        Operator { ratio: 1., level: 0.5 },
+        Operator { ratio: 14., level: 0.25 },
+    ]);
+    Builder::osc(Fm { operators, algorithm: Algorithm::TwoStacks, index: 0.3, feedback: 0.2 })
+            .adsr(0., 1.5, 0.2, 0.4).curves(Linear, Exponential, Exponential).mod_y(Oscillator(FmIndex))
+            .velocity_curve(VelocityCurve::Exponential).modulate(ModSource::Velocity, Oscillator(FmIndex), 0.3).build()
+}
+
+pub fn bell() -> instrument::Specs {
+    let operators = Box::new([
+        Operator { ratio: 1., level: 1. },
+        Operator { ratio: 3.5, level: 0.8 },
+        Operator { ratio: 1., level: 0. },
+        Operator { ratio: 1., level: 0. },
+    ]);
+    Builder::osc(Fm { operators, algorithm: Algorithm::Stack, index: 0.4, feedback: 0. })
+            .adsr(0., 3., 0., 2.).curves(Linear, Exponential, Exponential).mod_y(Oscillator(FmIndex)).build()
+}
+
+pub fn sync_lead() -> instrument::Specs {
+    Builder::osc(Basic(Saw, BandLimited))
+            .add_osc(Basic(Saw, Naive)).tune(1, 1, 0, 0.).level(1, 0.7).sync()
+            .voice_mode(VoiceMode::Legato).glide(0.08).lfo(lfo::Specs { delay: 0.3, ..lfo::Specs::simple(5.) }, Pitch, 0.15)
+            .mod_y(Volume).build()
+}
+
+pub fn sub_bass() -> instrument::Specs {
+    Builder::osc(Basic(Saw, BandLimited))
+            .add_osc(Pulse(0.3, BandLimited)).tune(1, 0, 0, 7.).level(1, 0.5)
+            .sub(Square, -1, 0.6).voice_mode(VoiceMode::Mono)
+            .filter(filter::Specs { model: filter::Model::Ladder { drive: 0.3 }, filter_type: filter::TypeSpec::LPF,
+                                   cutoff: 0.03, resonance: 0.3, key_tracking: 0.5,
+                                   velocity_sensitivity: 0.5, ..Default::default() })
+            .filter_envelope(0., 0.3, 0.1, 0.1, 0.3)
+            .adsr(0., 0.2, 0.6, 0.1).build()
+}
+
+pub fn pluck() -> instrument::Specs {
+    Builder::osc(Pulse(0.5, BandLimited))
+            .filter(filter::Specs { cutoff: 0.05, resonance: 0.2, ..Default::default() })
+            .filter_envelope(0., 0.2, 0., 0.2, 0.4)
+            .mod_envelope(Dahdsr::new(0., 0., 0.02, 0.1, 0., 0.), Oscillator(PulseDuty), -0.4)
+            .adsr(0., 0.4, 0., 0.3).curves(Linear, Exponential, Exponential).build()
 }
 
 pub fn supersaw() -> instrument::Specs {
-    Builder::osc(Mix { n_voices: 8, detune_amount: 3., specs: Saw, random_seed: 0 })
-            .lfo(lfo::Specs::simple(0.1), Filter(Cutoff), 0.8).build()
+    Builder::osc(Mix { n_voices: 8, detune_amount: 3., specs: Saw, quality: BandLimited, random_seed: 0, spread: 0.8 })
+            .lfo(lfo::Specs::simple(0.1), Filter(Cutoff), 0.4)
+            .mod_y(Oscillator(MixThickness)).build()
 }
 
 fn octaves() -> Phrase {
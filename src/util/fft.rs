@@ -0,0 +1,84 @@
+use std::f64::consts::PI;
+
+/// In-place radix-2 Cooley-Tukey FFT. The length must be a power of two.
+pub fn fft(re: &mut [f64], im: &mut [f64]) {
+    transform(re, im, false)
+}
+
+/// Inverse of `fft`, normalized so that `ifft(fft(x)) == x`.
+pub fn ifft(re: &mut [f64], im: &mut [f64]) {
+    transform(re, im, true);
+    let n = re.len() as f64;
+    re.iter_mut().chain(im.iter_mut()).for_each(|v| *v /= n);
+}
+
+/// Power of each frequency bin from DC up to (excluding) Nyquist
+pub fn power_spectrum(signal: &[f64]) -> Vec<f64> {
+    let mut re = signal.to_vec();
+    let mut im = vec![0.; signal.len()];
+    fft(&mut re, &mut im);
+    re.iter().zip(im.iter())
+        .take(signal.len() / 2)
+        .map(|(r, i)| r * r + i * i)
+        .collect()
+}
+
+fn transform(re: &mut [f64], im: &mut [f64], inverse: bool) {
+    let n = re.len();
+    assert!(n.is_power_of_two() && im.len() == n, "length was: {}", n);
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let sign = if inverse { 1. } else { -1. };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2. * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_bin() {
+        let signal: Vec<f64> = (0..64).map(|i| (2. * PI * 5. * i as f64 / 64.).sin()).collect();
+        let spectrum = power_spectrum(&signal);
+        let peak = spectrum.iter().enumerate()
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap()).map(|(i, _)| i);
+        assert_eq!(peak, Some(5));
+    }
+
+    #[test]
+    fn round_trip() {
+        let signal: Vec<f64> = (0..32).map(|i| (i as f64 * 0.7).cos() + 0.1 * i as f64).collect();
+        let mut re = signal.clone();
+        let mut im = vec![0.; 32];
+        fft(&mut re, &mut im);
+        ifft(&mut re, &mut im);
+        re.iter().zip(signal.iter()).for_each(|(a, b)| assert!((a - b).abs() < 1e-12));
+    }
+}
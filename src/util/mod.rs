@@ -1,3 +1,5 @@
 pub mod duration;
 pub mod reckless_float;
-pub mod range_map;
\ No newline at end of file
+pub mod range_map;
+pub mod fft;
+pub mod ring_buffer;
\ No newline at end of file
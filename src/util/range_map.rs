@@ -33,13 +33,21 @@ impl <T> CyclicRangeMap<T> {
     }
 
     pub fn range(&self, from: f64, to: f64) -> Vec<&T> {
+        let mut values = vec![];
+        self.for_each_in_range(from, to, |value| values.push(value));
+        values
+    }
+
+    /// Same as `range` without collecting the values
+    pub fn for_each_in_range<'a>(&'a self, from: f64, to: f64, f: impl FnMut(&'a T)) {
         let full_cycles = ((to - from) / self.end).floor() as usize;
         let cycled_from = from % self.end;
         let cycled_to = to % self.end;
 
         if to < from {
-            vec![]
-        } else if full_cycles > 0 {
+            return;
+        }
+        if full_cycles > 0 {
             let middle = self.tree_map.iter()
                 .flat_map(|(_, v)| v).cycle().take(self.tree_map.len() * full_cycles);
             let end = self.tree_map
@@ -50,9 +58,9 @@ impl <T> CyclicRangeMap<T> {
                 let begin = self.tree_map
                     .range(RecklessFloat(cycled_from)..)
                     .flat_map(|(_, v)| v);
-                begin.chain(tail).collect()
+                begin.chain(tail).for_each(f)
             } else {
-                tail.collect()
+                tail.for_each(f)
             }
         } else if cycled_to < cycled_from {
             let begin = self.tree_map
@@ -61,11 +69,11 @@ impl <T> CyclicRangeMap<T> {
             let end = self.tree_map
                 .range(..RecklessFloat(cycled_to))
                 .flat_map(|(_, v)| v);
-            begin.chain(end).collect()
+            begin.chain(end).for_each(f)
         } else {
             self.tree_map
                 .range(RecklessFloat(cycled_from)..RecklessFloat(cycled_to))
-                .flat_map(|(_, v)| v).collect()
+                .flat_map(|(_, v)| v).for_each(f)
         }
 
     }
@@ -0,0 +1,147 @@
+use std::{thread, time::Duration};
+use std::sync::{Arc, atomic::{AtomicU64, AtomicUsize, Ordering::{Acquire, Release, Relaxed}}};
+use crate::core::synth::Frame;
+
+/// How long the producer sleeps while the buffer is full
+const WAIT: Duration = Duration::from_millis(1);
+
+/// Lock-free queue of frames from one producer thread to one consumer thread,
+/// so the audio callback never waits on the synth
+pub fn ring_buffer(capacity: usize) -> (Producer, Consumer) {
+    assert!(capacity > 0, "capacity was: {}", capacity);
+    let slots = || (0..capacity).map(|_| AtomicU64::new(0)).collect();
+    let shared = Arc::new(Shared {
+        left: slots(),
+        right: slots(),
+        written: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+    });
+    (Producer { shared: shared.clone() }, Consumer { shared })
+}
+
+struct Shared {
+    left: Box<[AtomicU64]>,
+    right: Box<[AtomicU64]>,
+    /// Frames written and read so far, both wrap around
+    written: AtomicUsize,
+    read: AtomicUsize,
+}
+
+impl Shared {
+    fn capacity(&self) -> usize {
+        self.left.len()
+    }
+
+    fn slot(&self, count: usize) -> usize {
+        count % self.capacity()
+    }
+}
+
+pub struct Producer {
+    shared: Arc<Shared>,
+}
+
+impl Producer {
+    /// Writes as many frames as fit, returns how many
+    pub fn push(&mut self, frames: &[Frame]) -> usize {
+        let shared = &self.shared;
+        let written = shared.written.load(Relaxed);
+        let free = shared.capacity() - written.wrapping_sub(shared.read.load(Acquire));
+        let count = free.min(frames.len());
+        for (i, frame) in frames[..count].iter().enumerate() {
+            let slot = shared.slot(written.wrapping_add(i));
+            shared.left[slot].store(frame.left.to_bits(), Relaxed);
+            shared.right[slot].store(frame.right.to_bits(), Relaxed);
+        }
+        shared.written.store(written.wrapping_add(count), Release);
+        count
+    }
+
+    /// Waits for room until all frames are written
+    pub fn push_all(&mut self, mut frames: &[Frame]) -> Result<(), String> {
+        while !frames.is_empty() {
+            if Arc::strong_count(&self.shared) == 1 {
+                return Err("Consumer hung up".to_string());
+            }
+            let count = self.push(frames);
+            frames = &frames[count..];
+            if count == 0 {
+                thread::sleep(WAIT);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Consumer {
+    shared: Arc<Shared>,
+}
+
+impl Consumer {
+    pub fn pop(&mut self) -> Option<Frame> {
+        let shared = &self.shared;
+        let read = shared.read.load(Relaxed);
+        if read == shared.written.load(Acquire) {
+            return None;
+        }
+        let slot = shared.slot(read);
+        let frame = Frame::new(f64::from_bits(shared.left[slot].load(Relaxed)),
+                               f64::from_bits(shared.right[slot].load(Relaxed)));
+        shared.read.store(read.wrapping_add(1), Release);
+        Some(frame)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.written.load(Acquire).wrapping_sub(self.shared.read.load(Relaxed))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(range: std::ops::Range<usize>) -> Vec<Frame> {
+        range.map(|i| Frame::new(i as f64, -(i as f64))).collect()
+    }
+
+    #[test]
+    fn fifo_up_to_capacity() {
+        let (mut producer, mut consumer) = ring_buffer(4);
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(producer.push(&frames(0..6)), 4);
+        assert_eq!(consumer.len(), 4);
+        assert_eq!(consumer.pop(), Some(Frame::new(0., 0.)));
+        assert_eq!(producer.push(&frames(4..6)), 1);
+        let popped: Vec<Frame> = std::iter::from_fn(|| consumer.pop()).collect();
+        assert_eq!(popped, frames(1..5));
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn across_threads() {
+        let (mut producer, mut consumer) = ring_buffer(16);
+        let sent = frames(0..10_000);
+        let expected = sent.clone();
+        let writer = thread::spawn(move || sent.chunks(7).for_each(|chunk| producer.push_all(chunk).unwrap()));
+        let mut received = Vec::with_capacity(expected.len());
+        while received.len() < expected.len() {
+            match consumer.pop() {
+                Some(frame) => received.push(frame),
+                None => thread::yield_now(),
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn fails_without_consumer() {
+        let (mut producer, consumer) = ring_buffer(2);
+        drop(consumer);
+        assert!(producer.push_all(&frames(0..1)).is_err());
+    }
+}
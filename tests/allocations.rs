@@ -0,0 +1,175 @@
+//! Rendering must not allocate once set up, so the audio thread never waits on the allocator
+
+use std::{alloc::{GlobalAlloc, Layout, System}, cell::Cell, thread, time::Duration};
+use rust_synth::core::{
+    control::{BLOCK_SIZE, sheet_music, synth::{self, Command::*, id}, tools},
+    music_theory::{Hz, pitch::Pitch, pitch_class::PitchClass},
+    sheet_music::{playing_music::PlayingMusic, sheet_music::{SheetMusic, Section, Voice}},
+    synth::Frame,
+    tools::{arpeggiator::Arpeggiator, loops},
+};
+use rust_synth::preset;
+
+const SAMPLE_RATE: Hz = 44100.;
+
+/// Counts allocations on the current thread only, other tests run in parallel
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn count_allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+fn play(synth: &mut synth::State, block: &mut [Frame], index: usize) {
+    let pitch = Pitch::from_index(48 + index % 24);
+    synth.interpret(NoteOn(pitch, 1., id(pitch)));
+    (0..10).for_each(|_| synth.process(block));
+    synth.interpret(NoteOff(id(pitch)));
+}
+
+#[test]
+fn synth_renders_without_allocating() {
+    for specs in preset::instruments() {
+        let mut synth = synth::State::new(SAMPLE_RATE);
+        synth.interpret(SetPatch(specs));
+        let mut block = [Frame::default(); BLOCK_SIZE];
+        synth.process(&mut block);
+
+        let allocations = count_allocations(|| (0..50).for_each(|i| play(&mut synth, &mut block, i)));
+        assert_eq!(allocations, 0);
+    }
+}
+
+#[test]
+fn loops_record_and_play_without_allocating() {
+    let mut loops = loops::Manager::new(SAMPLE_RATE);
+    for index in [0, 1, 0] {
+        let recording = count_allocations(|| {
+            loops.interpret(loops::Command::ToggleRecording(index));
+            (0..1000).for_each(|i| loops.write(Frame::mono(f64::from(i))));
+            loops.interpret(loops::Command::ToggleRecording(index));
+            loops.interpret(loops::Command::TogglePlayback(index));
+        });
+        assert_eq!(recording, 0);
+        while loops.next_frame() == Frame::default() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let playing = count_allocations(|| (0..3000).for_each(|_| { loops.next_frame(); }));
+        assert_eq!(playing, 0);
+        loops.interpret(loops::Command::TogglePlayback(index));
+    }
+}
+
+#[test]
+fn arpeggiator_ticks_without_allocating() {
+    let mut arpeggiator = Arpeggiator::from_phrase(PitchClass::C, preset::sequences()[0].clone());
+    let pitch = Pitch::from_index(60);
+    arpeggiator.interpret(NoteOn(pitch, 1., id(pitch)));
+    let mut commands = Vec::with_capacity(16);
+    let mut played = 0;
+    let allocations = count_allocations(|| (0..1000).for_each(|i| {
+        arpeggiator.next(f64::from(i) / 100., f64::from(i + 1) / 100., &mut commands);
+        played += commands.len();
+        commands.clear();
+    }));
+    assert!(played > 0);
+    assert_eq!(allocations, 0);
+}
+
+/// Two channels playing a note every 10 ms, each one second long
+fn music() -> SheetMusic {
+    let voice = |channel| {
+        let events = (0..100).flat_map(|i| {
+            let pitch = Pitch::from_index(48 + i % 24);
+            [(NoteOn(pitch, 1., id(pitch)), i as u64 * 10), (NoteOff(id(pitch)), i as u64 * 10 + 5)]
+        }).collect();
+        Voice::new(events, channel)
+    };
+    SheetMusic {
+        sections: vec![Section { tick_duration: Duration::from_millis(1), ..Default::default() }],
+        voices: vec![voice(0), voice(1)],
+        end: 1000,
+        ..Default::default()
+    }
+}
+
+/// Elapsed time after `block` blocks
+fn block_time(block: usize) -> Duration {
+    Duration::from_secs_f64((block * BLOCK_SIZE) as f64 / SAMPLE_RATE)
+}
+
+#[test]
+fn sheet_music_plays_without_allocating() {
+    let mut playing = PlayingMusic::new(music());
+    let mut commands = Vec::with_capacity(16);
+    let mut played = 0;
+    let allocations = count_allocations(|| (0..700).for_each(|block| {
+        playing.next(block_time(block), &mut commands);
+        played += commands.len();
+        commands.clear();
+    }));
+    assert_eq!(played, 400);
+    assert_eq!(allocations, 0);
+
+    let mut state = sheet_music::State::new(SAMPLE_RATE, music());
+    let mut block = [Frame::default(); BLOCK_SIZE];
+    state.tick_music(block_time(0));
+    state.process(&mut block);
+    let mut loudest: f64 = 0.;
+    let allocations = count_allocations(|| (1..700).for_each(|i| {
+        state.tick_music(block_time(i));
+        state.process(&mut block);
+        loudest = block.iter().fold(loudest, |max, frame| max.max(frame.left.abs()));
+    }));
+    assert!(loudest > 0.01, "loudest: {}", loudest);
+    assert_eq!(allocations, 0);
+}
+
+const C_MAJOR: [usize; 7] = [48, 50, 52, 53, 55, 57, 59];
+
+#[test]
+fn tools_play_without_allocating() {
+    let mut state = tools::State::new(SAMPLE_RATE);
+    let mut block = [Frame::default(); BLOCK_SIZE];
+    state.process(&mut block);
+    for arpeggiator in [None, Some(preset::sequences()[0].clone())] {
+        state.interpret(tools::Command::SetPatch(tools::Patch::ArpeggiatorPhrase(arpeggiator)));
+        let mut loudest: f64 = 0.;
+        let allocations = count_allocations(|| (0..50).for_each(|i| {
+            // The transposer only takes notes in its scale
+            let pitch = Pitch::from_index(C_MAJOR[i % C_MAJOR.len()]);
+            state.interpret(tools::Command::Instrument(NoteOn(pitch, 1., id(pitch))));
+            for _ in 0..10 {
+                state.tick_arpeggiator();
+                state.process(&mut block);
+                loudest = block.iter().fold(loudest, |max, frame| max.max(frame.left.abs()));
+                thread::sleep(Duration::from_micros(200));
+            }
+            state.interpret(tools::Command::Instrument(NoteOff(id(pitch))));
+        }));
+        assert!(loudest > 0.01, "loudest: {}", loudest);
+        assert_eq!(allocations, 0);
+    }
+}